@@ -3,25 +3,16 @@ use std::fs::File;
 use std::io::Write;
 use std::path::PathBuf;
 
-fn main() {
-    // Put `memory.x` in our output directory and ensure it's
-    // on the linker search path.
-    let out = &PathBuf::from(env::var_os("OUT_DIR").unwrap());
-    File::create(out.join("memory.x"))
-        .unwrap()
-        .write_all(include_bytes!("memory.x"))
-        .unwrap();
-    println!("cargo:rustc-link-search={}", out.display());
+mod manifest;
 
-    // By default, Cargo will re-run a build script whenever
-    // any file in the project changes. By specifying `memory.x`
-    // here, we ensure the build script is only re-run when
-    // `memory.x` is changed.
-    println!("cargo:rerun-if-changed=memory.x");
+fn main() {
+    write_heap_size();
+    write_module_registry();
 
-    println!("cargo:rustc-link-arg-bins=--nmagic");
-    println!("cargo:rustc-link-arg-bins=-Tlink.x");
-    println!("cargo:rustc-link-arg-bins=-Tdefmt.x");
+    // the linker scripts are only needed for the boards, the host build is a normal executable
+    if env::var("CARGO_CFG_TARGET_OS").as_deref() == Ok("none") {
+        link_for_mcu();
+    }
 
     #[cfg(feature = "engine-wamr")]
     {
@@ -72,6 +63,11 @@ fn main() {
 
         // build the WAMR static library
         let mut cc_build = cc::Build::new();
+        // an XIP module (`wamr-xip`) calls the runtime for what LLVM intrinsics would do otherwise
+        let xip = env::var_os("CARGO_FEATURE_WAMR_XIP").is_some();
+        if xip {
+            cc_build.file(wamr_dir.join("core/iwasm/aot/aot_intrinsic.c"));
+        }
         cc_build
             .include(&wamr_include_dir)
             .include(&embassy_platform_dir)
@@ -115,7 +111,10 @@ fn main() {
             .define("BUILD_TARGET_THUMB", None)
             .define("BUILD_TARGET", Some("\"THUMBV7\"")) // for the AOT mode: must match our target (and the info we gave wamrc when compiling the module)
             .define("WASM_ENABLE_QUICK_AOT_ENTRY", Some("0")) // Disable quick entry optimization
-            .define("WASM_ENABLE_AOT_INTRINSICS", Some("0")) // Disable quick entry optimization
+            .define(
+                "WASM_ENABLE_AOT_INTRINSICS",
+                Some(if xip { "1" } else { "0" }),
+            )
             .define("WASM_ENABLE_LOG", Some("0"))
             .flag("-Os")
             .flag("-ffunction-sections")
@@ -127,3 +126,232 @@ fn main() {
         println!("cargo:rustc-link-search=native={}", out_path.display()); // search for the static library in the output directory (so that we know where the file is we just mentioned)
     }
 }
+
+/// The heap size in bytes, set with `BENCHMARK_HEAP_SIZE` (`_` separators allowed)
+fn write_heap_size() {
+    const DEFAULT_HEAP_SIZE: usize = 200_000;
+
+    println!("cargo:rerun-if-env-changed=BENCHMARK_HEAP_SIZE");
+    let heap_size = match env::var("BENCHMARK_HEAP_SIZE") {
+        Ok(size) => match size.replace('_', "").parse::<usize>() {
+            Ok(size) if size > 0 => size,
+            _ => panic!("BENCHMARK_HEAP_SIZE must be a positive number of bytes, got '{size}'"),
+        },
+        Err(_) => DEFAULT_HEAP_SIZE,
+    };
+
+    // included by `src/allocator.rs`
+    let out = PathBuf::from(env::var_os("OUT_DIR").unwrap());
+    std::fs::write(out.join("heap_size.rs"), heap_size.to_string()).unwrap();
+}
+
+/// A format of the manifest and when an enabled engine loads it
+struct ManifestFormat {
+    /// As written in the manifest
+    name: &'static str,
+    /// The `Format` variant in `src/registry.rs`
+    variant: &'static str,
+    /// Loaded if all of these features are enabled...
+    features: &'static [&'static str],
+    /// ...and none of these
+    unless: &'static [&'static str],
+}
+
+impl ManifestFormat {
+    fn is_loaded(&self) -> bool {
+        let enabled = |feature: &&str| env::var_os(format!("CARGO_FEATURE_{feature}")).is_some();
+        self.features.iter().all(enabled) && !self.unless.iter().any(enabled)
+    }
+}
+
+const FORMATS: &[ManifestFormat] = &[
+    ManifestFormat {
+        name: "wasm",
+        variant: "Wasm",
+        features: &["ENGINE_WASMI"],
+        unless: &[],
+    },
+    ManifestFormat {
+        name: "tw",
+        variant: "Tinywasm",
+        features: &["ENGINE_TINYWASM"],
+        unless: &[],
+    },
+    ManifestFormat {
+        name: "cwasm",
+        variant: "Cwasm",
+        features: &["ENGINE_WASMTIME"],
+        unless: &["BOARD_HOST"],
+    },
+    ManifestFormat {
+        name: "host.cwasm",
+        variant: "HostCwasm",
+        features: &["ENGINE_WASMTIME", "BOARD_HOST"],
+        unless: &[],
+    },
+    ManifestFormat {
+        name: "aot",
+        variant: "Aot",
+        features: &["ENGINE_WAMR"],
+        unless: &["WAMR_XIP"],
+    },
+    ManifestFormat {
+        name: "xip.aot",
+        variant: "XipAot",
+        features: &["WAMR_XIP"],
+        unless: &[],
+    },
+];
+
+/// An artifact of the module manifest, its path resolved
+struct Artifact<'a> {
+    format: &'a str,
+    path: PathBuf,
+    /// Size and SHA-256 (lower-case hex), if the manifest has them
+    checksum: Option<(&'a str, &'a str)>,
+}
+
+/// Generates the list of guest modules (`src/registry.rs`) from the module manifest
+///
+/// The manifest is `../modules.manifest` (written by `precompile`) unless `BENCHMARK_MODULES` names
+/// another file. Every line is `<module> <format> <path> [<size> <sha256>]`, the path relative to
+/// the manifest; `#` starts a comment. Only the formats an enabled engine loads are embedded, and
+/// each of them has to exist for every module, with the size and hash the manifest says.
+fn write_module_registry() {
+    println!("cargo:rerun-if-env-changed=BENCHMARK_MODULES");
+    let manifest_dir = PathBuf::from(env::var_os("CARGO_MANIFEST_DIR").unwrap());
+    let manifest = manifest_dir
+        .join(env::var("BENCHMARK_MODULES").unwrap_or_else(|_| "../modules.manifest".to_string()));
+    println!("cargo:rerun-if-changed={}", manifest.display());
+    let text = std::fs::read_to_string(&manifest).unwrap_or_else(|err| {
+        panic!(
+            "failed to read {} ({err}); run `build_modules.sh` first",
+            manifest.display()
+        )
+    });
+    let base = manifest.parent().unwrap();
+
+    // (module, artifacts) in the order of the manifest
+    let mut modules: Vec<(&str, Vec<Artifact>)> = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = manifest::parse_line(line)
+            .unwrap_or_else(|err| panic!("{}:{}: {err}", manifest.display(), number + 1));
+        let Some(manifest::Line {
+            module: name,
+            format,
+            path,
+            checksum,
+        }) = line
+        else {
+            continue;
+        };
+        if !FORMATS.iter().any(|known| known.name == format) {
+            panic!(
+                "{}:{}: unknown format `{format}`",
+                manifest.display(),
+                number + 1
+            );
+        }
+        let index = match modules.iter().position(|(module, _)| *module == name) {
+            Some(index) => index,
+            None => {
+                modules.push((name, Vec::new()));
+                modules.len() - 1
+            }
+        };
+        let artifacts = &mut modules[index].1;
+        if artifacts.iter().any(|artifact| artifact.format == format) {
+            panic!(
+                "{}:{}: `{name}` has two `{format}` artifacts",
+                manifest.display(),
+                number + 1
+            );
+        }
+        artifacts.push(Artifact {
+            format,
+            path: base.join(path),
+            checksum,
+        });
+    }
+
+    let mut code = String::from("pub const MODULES: &[GuestModule] = &[\n");
+    for (name, artifacts) in &modules {
+        code += &format!("    GuestModule {{\n        name: {name:?},\n        artifacts: &[\n");
+        for format in FORMATS.iter().filter(|format| format.is_loaded()) {
+            let Some(Artifact { path, checksum, .. }) = artifacts
+                .iter()
+                .find(|artifact| artifact.format == format.name)
+            else {
+                panic!(
+                    "module `{name}` has no `{}` artifact, which an enabled engine loads",
+                    format.name
+                );
+            };
+            let bytes = std::fs::read(path).unwrap_or_else(|err| {
+                panic!(
+                    "failed to read {} (the `{}` artifact of `{name}`): {err}",
+                    path.display(),
+                    format.name
+                )
+            });
+            if let Some((size, sha256)) = checksum {
+                if bytes.len().to_string() != *size || sha256_hex(&bytes) != *sha256 {
+                    panic!(
+                        "{} changed since the manifest was written; run `build_modules.sh` again",
+                        path.display()
+                    );
+                }
+            }
+            println!("cargo:rerun-if-changed={}", path.display());
+            code += &format!(
+                "            (Format::{}, include_bytes!({:?})),\n",
+                format.variant,
+                path.display().to_string()
+            );
+        }
+        code += "        ],\n    },\n";
+    }
+    code += "];\n";
+
+    // included by `src/registry.rs`
+    let out = PathBuf::from(env::var_os("OUT_DIR").unwrap());
+    std::fs::write(out.join("modules.rs"), code).unwrap();
+}
+
+fn sha256_hex(bytes: &[u8]) -> String {
+    use sha2::{Digest, Sha256};
+
+    Sha256::digest(bytes)
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+fn link_for_mcu() {
+    // QEMU has a different memory map than the boards
+    let memory_x: &[u8] = if env::var_os("CARGO_FEATURE_BOARD_QEMU").is_some() {
+        include_bytes!("memory-qemu.x")
+    } else {
+        include_bytes!("memory.x")
+    };
+
+    // Put `memory.x` in our output directory and ensure it's
+    // on the linker search path.
+    let out = &PathBuf::from(env::var_os("OUT_DIR").unwrap());
+    File::create(out.join("memory.x"))
+        .unwrap()
+        .write_all(memory_x)
+        .unwrap();
+    println!("cargo:rustc-link-search={}", out.display());
+
+    // By default, Cargo will re-run a build script whenever
+    // any file in the project changes. By specifying the memory
+    // layouts here, we ensure the build script is only re-run when
+    // one of them is changed.
+    println!("cargo:rerun-if-changed=memory.x");
+    println!("cargo:rerun-if-changed=memory-qemu.x");
+
+    println!("cargo:rustc-link-arg-bins=--nmagic");
+    println!("cargo:rustc-link-arg-bins=-Tlink.x");
+    println!("cargo:rustc-link-arg-bins=-Tdefmt.x");
+}
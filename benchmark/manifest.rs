@@ -0,0 +1,35 @@
+//! The lines of the module manifest (see `src/registry.rs`)
+//!
+//! `build.rs` reads the manifest with this, and the tests of `precompile`, which writes it, parse
+//! what they wrote with it as well, so writer and reader can not drift apart.
+
+/// An artifact as listed in the manifest: `<module> <format> <path> [<size> <sha256>]`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Line<'a> {
+    pub module: &'a str,
+    pub format: &'a str,
+    /// Relative to the manifest
+    pub path: &'a str,
+    /// Size and SHA-256 (lower-case hex) of the artifact, if they are listed
+    pub checksum: Option<(&'a str, &'a str)>,
+}
+
+/// Parses one line of the manifest; `Ok(None)` if it is empty or only a comment (`# ...`)
+pub fn parse_line(line: &str) -> Result<Option<Line<'_>>, &'static str> {
+    let line = line.split('#').next().unwrap_or_default().trim();
+    if line.is_empty() {
+        return Ok(None);
+    }
+    let fields: Vec<_> = line.split_whitespace().collect();
+    let (module, format, path, checksum) = match fields[..] {
+        [module, format, path] => (module, format, path, None),
+        [module, format, path, size, sha256] => (module, format, path, Some((size, sha256))),
+        _ => return Err("expected `<module> <format> <path> [<size> <sha256>]`"),
+    };
+    Ok(Some(Line {
+        module,
+        format,
+        path,
+        checksum,
+    }))
+}
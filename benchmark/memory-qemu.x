@@ -0,0 +1,21 @@
+MEMORY
+{
+  /* NOTE 1 K = 1 KiBi = 1024 bytes */
+  /* QEMU mps2-an505 (secure aliases): code goes to SSRAM1, data to SSRAM2 */
+  /* RAM is cut down from 2M to the 256K of the NRF5340, so the numbers stay comparable to the boards */
+  FLASH : ORIGIN = 0x10000000, LENGTH = 1024K
+  RAM : ORIGIN = 0x38000000, LENGTH = 256K
+}
+
+/* The task arena of embassy-executor gets a section of its own, so `src/stack.rs` can measure it.
+   The runtime neither loads nor zeroes this section, `stack::clear_task_arena` does at boot. */
+SECTIONS
+{
+  .task_arena (NOLOAD) : ALIGN(8)
+  {
+    __stask_arena = .;
+    KEEP(*(.bss.*embassy_executor*ARENA*));
+    . = ALIGN(4);
+    __etask_arena = .;
+  } > RAM
+} INSERT BEFORE .bss;
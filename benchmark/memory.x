@@ -4,4 +4,16 @@ MEMORY
   /* These values correspond to the NRF5340 */
   FLASH : ORIGIN = 0x00000000, LENGTH = 1024K
   RAM : ORIGIN = 0x20000000, LENGTH = 256K
-}
\ No newline at end of file
+}
+/* The task arena of embassy-executor gets a section of its own, so `src/stack.rs` can measure it.
+   The runtime neither loads nor zeroes this section, `stack::clear_task_arena` does at boot. */
+SECTIONS
+{
+  .task_arena (NOLOAD) : ALIGN(8)
+  {
+    __stask_arena = .;
+    KEEP(*(.bss.*embassy_executor*ARENA*));
+    . = ALIGN(4);
+    __etask_arena = .;
+  } > RAM
+} INSERT BEFORE .bss;
@@ -0,0 +1,215 @@
+//! The allocator behind the heap and the size of the heap
+//!
+//! Which allocator serves the heap is chosen with one of the `alloc-*` features:
+//!
+//! - `alloc-llff` (the default): the linked-list first-fit allocator of `embedded-alloc`
+//! - `alloc-tlsf`: the two-level segregated fit allocator of `embedded-alloc` (bounded time,
+//!   size classes)
+//! - `alloc-dlmalloc`: Doug Lea's allocator, as used by Rust on `wasm32`
+//! - `alloc-bump`: never reuses freed memory, so its heap peak is the sum of all allocations
+//!
+//! The heap size is fixed at build time: set `BENCHMARK_HEAP_SIZE` (in bytes) when building, see
+//! `build.rs`. Both are part of every result record (see `crate::results`), so runs with different
+//! allocators or heaps can be told apart.
+//!
+//! Every backend has the `empty()`/`init(start, size)` API of `embedded-alloc`, so `main` sets up
+//! [`Backend`] without knowing which one it is.
+
+use core::alloc::{GlobalAlloc, Layout};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+#[cfg(any(
+    all(feature = "alloc-llff", feature = "alloc-tlsf"),
+    all(feature = "alloc-llff", feature = "alloc-dlmalloc"),
+    all(feature = "alloc-llff", feature = "alloc-bump"),
+    all(feature = "alloc-tlsf", feature = "alloc-dlmalloc"),
+    all(feature = "alloc-tlsf", feature = "alloc-bump"),
+    all(feature = "alloc-dlmalloc", feature = "alloc-bump"),
+))]
+compile_error!("only one of the `alloc-*` features can be enabled");
+
+/// Size of the heap in bytes (`BENCHMARK_HEAP_SIZE` at build time, 200 000 if not set)
+pub const HEAP_SIZE: usize = include!(concat!(env!("OUT_DIR"), "/heap_size.rs"));
+
+/// The allocator selected by the `alloc-*` features
+#[cfg(feature = "alloc-tlsf")]
+pub type Backend = embedded_alloc::TlsfHeap;
+#[cfg(feature = "alloc-dlmalloc")]
+pub type Backend = DlmallocHeap;
+#[cfg(feature = "alloc-bump")]
+pub type Backend = BumpHeap;
+#[cfg(not(any(
+    feature = "alloc-tlsf",
+    feature = "alloc-dlmalloc",
+    feature = "alloc-bump"
+)))]
+pub type Backend = embedded_alloc::LlffHeap;
+
+/// Name of the selected allocator, as it appears in the results
+pub const NAME: &str = if cfg!(feature = "alloc-tlsf") {
+    "tlsf"
+} else if cfg!(feature = "alloc-dlmalloc") {
+    "dlmalloc"
+} else if cfg!(feature = "alloc-bump") {
+    "bump"
+} else {
+    "llff"
+};
+
+/// Hands out memory from the front of the heap and never takes it back
+///
+/// The cheapest allocator there is. Since freed blocks are lost, it shows how much an engine
+/// allocates over a run in total, compared to how much it holds at once with the other allocators.
+pub struct BumpHeap {
+    next: AtomicUsize,
+    end: AtomicUsize,
+}
+
+impl BumpHeap {
+    pub const fn empty() -> Self {
+        Self {
+            next: AtomicUsize::new(0),
+            end: AtomicUsize::new(0),
+        }
+    }
+
+    /// Hands out `size` bytes from `start` on
+    ///
+    /// # Safety
+    ///
+    /// The memory has to be valid and unused, and this must be called only once, before the first
+    /// allocation.
+    pub unsafe fn init(&self, start: usize, size: usize) {
+        self.next.store(start, Ordering::Relaxed);
+        self.end.store(start + size, Ordering::Relaxed);
+    }
+}
+
+unsafe impl GlobalAlloc for BumpHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let end = self.end.load(Ordering::Relaxed);
+        let mut next = self.next.load(Ordering::Relaxed);
+        loop {
+            // the alignment is a power of two, so rounding up is masking
+            let Some(start) = next.checked_add(layout.align() - 1) else {
+                return core::ptr::null_mut();
+            };
+            let start = start & !(layout.align() - 1);
+            let block_end = match start.checked_add(layout.size()) {
+                Some(block_end) if block_end <= end => block_end,
+                _ => return core::ptr::null_mut(),
+            };
+            match self.next.compare_exchange_weak(
+                next,
+                block_end,
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return start as *mut u8,
+                Err(current) => next = current,
+            }
+        }
+    }
+
+    unsafe fn dealloc(&self, _ptr: *mut u8, _layout: Layout) {
+        // freed memory is never reused
+    }
+}
+
+#[cfg(feature = "alloc-dlmalloc")]
+pub use dlmalloc_backend::DlmallocHeap;
+
+#[cfg(feature = "alloc-dlmalloc")]
+mod dlmalloc_backend {
+    use core::alloc::{GlobalAlloc, Layout};
+    use core::cell::RefCell;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    use critical_section::Mutex;
+    use dlmalloc::Dlmalloc;
+
+    // dlmalloc asks its system allocator for memory without a way to pass it our region, so the
+    // region lives here; there is only one heap, the global allocator
+    static REGION_START: AtomicUsize = AtomicUsize::new(0);
+    /// Set to 0 once dlmalloc got the region
+    static REGION_SIZE: AtomicUsize = AtomicUsize::new(0);
+
+    /// The "system" dlmalloc gets its memory from: the heap region, handed over all at once
+    struct Region;
+
+    unsafe impl dlmalloc::Allocator for Region {
+        fn alloc(&self, _size: usize) -> (*mut u8, usize, u32) {
+            // dlmalloc works with whatever it gets, even less than it asked for
+            match REGION_SIZE.swap(0, Ordering::Relaxed) {
+                0 => (core::ptr::null_mut(), 0, 0),
+                size => (REGION_START.load(Ordering::Relaxed) as *mut u8, size, 0),
+            }
+        }
+
+        fn remap(&self, _ptr: *mut u8, _old: usize, _new: usize, _can_move: bool) -> *mut u8 {
+            core::ptr::null_mut()
+        }
+
+        fn free_part(&self, _ptr: *mut u8, _old: usize, _new: usize) -> bool {
+            false
+        }
+
+        fn free(&self, _ptr: *mut u8, _size: usize) -> bool {
+            false
+        }
+
+        fn can_release_part(&self, _flags: u32) -> bool {
+            false
+        }
+
+        fn allocates_zeros(&self) -> bool {
+            false
+        }
+
+        fn page_size(&self) -> usize {
+            4096
+        }
+    }
+
+    /// dlmalloc in the heap region, behind a critical section
+    pub struct DlmallocHeap {
+        heap: Mutex<RefCell<Dlmalloc<Region>>>,
+    }
+
+    impl DlmallocHeap {
+        pub const fn empty() -> Self {
+            Self {
+                heap: Mutex::new(RefCell::new(Dlmalloc::new_with_allocator(Region))),
+            }
+        }
+
+        /// Lets dlmalloc use `size` bytes from `start` on
+        ///
+        /// # Safety
+        ///
+        /// The memory has to be valid and unused, and this must be called only once, before the
+        /// first allocation.
+        pub unsafe fn init(&self, start: usize, size: usize) {
+            REGION_START.store(start, Ordering::Relaxed);
+            REGION_SIZE.store(size, Ordering::Relaxed);
+        }
+    }
+
+    unsafe impl GlobalAlloc for DlmallocHeap {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            critical_section::with(|cs| unsafe {
+                self.heap
+                    .borrow_ref_mut(cs)
+                    .malloc(layout.size(), layout.align())
+            })
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            critical_section::with(|cs| unsafe {
+                self.heap
+                    .borrow_ref_mut(cs)
+                    .free(ptr, layout.size(), layout.align())
+            })
+        }
+    }
+}
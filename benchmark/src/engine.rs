@@ -0,0 +1,183 @@
+//! The interface every engine integration implements
+//!
+//! The engines differ a lot in how they are set up (wasmi and wasmtime have a linker, tinywasm
+//! collects imports, WAMR registers native symbols), but each of them can load a module, provide
+//! the host imports, instantiate and call an export. [`WasmEngine`] covers exactly those steps, and
+//! [`run`] drives any engine through the measured phases, so adding an engine or a workload does not
+//! mean copying the glue code again.
+
+use crate::error::BenchError;
+use crate::host::{HostStats, LogSink};
+use crate::hostcall;
+use crate::log;
+use crate::phase::{Phase, PhaseReport};
+use crate::registry::{Format, GuestModule};
+use crate::timing;
+use crate::workload;
+
+/// A Wasm value passed to or returned from a guest function
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(not(feature = "board-host"), derive(defmt::Format))]
+pub enum Value {
+    I32(i32),
+    I64(i64),
+    F32(f32),
+    F64(f64),
+}
+
+/// A Wasm runtime, set up the way the benchmark uses it
+///
+/// The engine owns the store (or whatever holds the runtime state), so dropping it tears the whole
+/// runtime down. Instances have to be dropped before their module, and both before their engine.
+pub trait WasmEngine: Sized {
+    /// The name of the engine in the reports
+    const NAME: &'static str;
+
+    /// The format this engine loads modules in (e.g. precompiled)
+    const FORMAT: Format;
+
+    /// Whether the engine validates a module in a step of its own (see [`Self::validate_module`]),
+    /// so the cost of validating is measured apart from loading
+    const VALIDATES: bool = false;
+
+    /// Whether the runtime checks the pointer and length the guest passes to the imports against
+    /// the guest memory itself, so hostile arguments trap before the host function runs and do not
+    /// show up in [`HostStats::traps`]
+    const CHECKS_IMPORT_ARGUMENTS: bool = false;
+
+    type Module;
+    type Instance;
+
+    /// Creates the engine and its store
+    fn new() -> Result<Self, BenchError>;
+
+    /// Checks that the module bytes are valid, if [`Self::VALIDATES`]; loading them afterwards
+    /// does not validate them again
+    fn validate_module(&mut self, _bytes: &[u8]) -> Result<(), BenchError> {
+        Ok(())
+    }
+
+    /// Loads the module bytes (in [`Self::FORMAT`])
+    ///
+    /// The bytes live as long as the firmware (on the boards, in flash), so an engine can use them
+    /// where they are instead of copying them into the heap (see [`crate::source`]).
+    fn load_module(&mut self, bytes: &'static [u8]) -> Result<Self::Module, BenchError>;
+
+    /// Provides the host functions the guest imports (`logging.log`)
+    fn link_host_functions(&mut self) -> Result<(), BenchError>;
+
+    /// Instantiates the module; engines that move the module into their store leave it empty
+    fn instantiate(&mut self, module: &mut Self::Module) -> Result<Self::Instance, BenchError>;
+
+    /// Calls the exported function `name` (functions return at most one value)
+    fn call(
+        &mut self,
+        instance: &mut Self::Instance,
+        name: &str,
+        args: &[Value],
+    ) -> Result<Option<Value>, BenchError>;
+
+    /// Size of the exported linear memory in bytes, if the instance exports one
+    fn memory_size(&mut self, instance: &Self::Instance) -> Option<usize>;
+
+    /// Where the host functions send the messages of the guest from now on
+    fn set_log_sink(&mut self, sink: LogSink);
+
+    /// What the host functions did so far (see [`crate::host::HostState`])
+    fn host_stats(&mut self) -> HostStats;
+}
+
+/// How often `run` logs per call: enough to see the host calls, bounded so the call returns
+pub const RUN_ITERATIONS: i32 = 10;
+
+/// Goes through the whole lifecycle of the module `guest` with the engine `E`, up to dropping
+/// instance, module and engine
+pub fn run<E: WasmEngine>(guest: &GuestModule, report: &mut PhaseReport) -> Result<(), BenchError> {
+    let bytes = artifact::<E>(guest)?;
+    let mut engine = report.try_measure(Phase::EngineInit, E::new)?;
+
+    if E::VALIDATES {
+        report.try_measure(Phase::Validate, || engine.validate_module(bytes))?;
+    }
+
+    let mut module = report.try_measure(Phase::ModuleLoad, || engine.load_module(bytes))?;
+
+    report.try_measure(Phase::Link, || engine.link_host_functions())?;
+
+    let mut instance =
+        report.try_measure(Phase::Instantiate, || engine.instantiate(&mut module))?;
+
+    report.try_measure(Phase::FirstCall, || call_run(&mut engine, &mut instance))?;
+
+    report.try_measure(Phase::SteadyState, || call_run(&mut engine, &mut instance))?;
+    log_cycles_per_iteration(report);
+
+    if let Some(size) = engine.memory_size(&instance) {
+        log::info!("{} | linear memory: {} bytes", E::NAME, size);
+    }
+
+    // other modules only have `run`
+    let failures = if guest.is_benchmark_module() {
+        workload::run_all(&mut engine, &mut instance, report)
+            + hostcall::run_all(&mut engine, &mut instance, report)
+            + hostcall::check_hostile_arguments(&mut engine, &mut instance, report)
+    } else {
+        0
+    };
+
+    let stats = engine.host_stats();
+    log::info!(
+        "{} | host: {} log calls, {} bytes read, {} messages rejected, {} traps",
+        E::NAME,
+        stats.log_calls,
+        stats.logged_bytes,
+        stats.rejected_messages,
+        stats.traps
+    );
+
+    report.measure(Phase::DropInstance, || drop(instance));
+    report.measure(Phase::DropModule, || drop(module));
+    report.measure(Phase::DropEngine, || drop(engine));
+
+    if failures > 0 {
+        return Err(BenchError::WrongResult("workloads or host calls failed"));
+    }
+    Ok(())
+}
+
+/// The bytes of `guest` in the format `E` loads
+pub(crate) fn artifact<E: WasmEngine>(guest: &GuestModule) -> Result<&'static [u8], BenchError> {
+    guest
+        .artifact(E::FORMAT)
+        .ok_or(BenchError::Unsupported("module not built for this engine"))
+}
+
+/// Calls `run`, which returns the number of iterations it did once it is done
+pub(crate) fn call_run<E: WasmEngine>(
+    engine: &mut E,
+    instance: &mut E::Instance,
+) -> Result<(), BenchError> {
+    match engine.call(instance, "run", &[Value::I32(RUN_ITERATIONS)])? {
+        Some(Value::I32(RUN_ITERATIONS)) => Ok(()),
+        _ => Err(BenchError::WrongResult(
+            "run did not complete its iterations",
+        )),
+    }
+}
+
+/// Spreads the cycles of the warmed-up `run` call over its iterations
+fn log_cycles_per_iteration(report: &PhaseReport) {
+    let Some(cycles) = report
+        .record(Phase::SteadyState)
+        .and_then(|record| record.cycles)
+    else {
+        return;
+    };
+    log::info!(
+        "{} | run: {} {} per iteration ({} iterations)",
+        report.engine(),
+        cycles / RUN_ITERATIONS as u64,
+        timing::UNIT,
+        RUN_ITERATIONS
+    );
+}
@@ -0,0 +1,115 @@
+//! The error type of the benchmark
+//!
+//! Every engine path returns a [`BenchError`], so a failure is reported the same way no matter
+//! which engine hit it. The messages of the engines are copied into a fixed buffer ([`Message`]):
+//! reporting an error must not allocate, since running out of heap is one of the failures we want
+//! to see (as [`BenchError::OutOfMemory`]) instead of crashing.
+
+use core::fmt::{self, Write};
+
+/// A short text copied out of an engine error (or an export name), cut off if it is too long
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Message {
+    bytes: [u8; Message::CAPACITY],
+    len: usize,
+}
+
+impl Message {
+    pub const CAPACITY: usize = 64;
+
+    /// Formats `value` into a new message
+    pub fn new(value: impl fmt::Display) -> Self {
+        let mut message = Self {
+            bytes: [0; Self::CAPACITY],
+            len: 0,
+        };
+        // writing into a message cuts the text off instead of failing
+        let _ = write!(message, "{}", value);
+        message
+    }
+
+    pub fn as_str(&self) -> &str {
+        // only whole characters are ever copied in
+        core::str::from_utf8(&self.bytes[..self.len]).unwrap_or_default()
+    }
+}
+
+impl Write for Message {
+    fn write_str(&mut self, text: &str) -> fmt::Result {
+        let mut end = text.len().min(Self::CAPACITY - self.len);
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        self.bytes[self.len..self.len + end].copy_from_slice(&text.as_bytes()[..end]);
+        self.len += end;
+        Ok(())
+    }
+}
+
+impl From<&str> for Message {
+    fn from(text: &str) -> Self {
+        Self::new(text)
+    }
+}
+
+impl fmt::Display for Message {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl fmt::Debug for Message {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+#[cfg(not(feature = "board-host"))]
+impl defmt::Format for Message {
+    fn format(&self, f: defmt::Formatter<'_>) {
+        defmt::write!(f, "{=str}", self.as_str())
+    }
+}
+
+/// Why a step of the benchmark failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(not(feature = "board-host"), derive(defmt::Format))]
+pub enum BenchError {
+    /// Creating the engine or its store failed
+    EngineInit(Message),
+    /// The module bytes could not be decoded, deserialized or loaded
+    Decode(Message),
+    /// The module is not valid Wasm
+    Invalid(Message),
+    /// The host functions could not be provided
+    Link(Message),
+    /// Instantiating the module failed
+    Instantiate(Message),
+    /// The guest trapped, or the engine could not call it
+    Trap(Message),
+    /// An allocation failed while the step ran
+    OutOfMemory,
+    /// The module has no export of that name
+    MissingExport(Message),
+    /// The harness asked for something the integration does not support (e.g. too many arguments)
+    Unsupported(&'static str),
+    /// The guest returned something other than what we expected
+    WrongResult(&'static str),
+}
+
+impl fmt::Display for BenchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BenchError::EngineInit(message) => write!(f, "engine init failed: {}", message),
+            BenchError::Decode(message) => write!(f, "failed to load module: {}", message),
+            BenchError::Invalid(message) => write!(f, "invalid module: {}", message),
+            BenchError::Link(message) => write!(f, "failed to link: {}", message),
+            BenchError::Instantiate(message) => write!(f, "failed to instantiate: {}", message),
+            BenchError::Trap(message) => write!(f, "trap: {}", message),
+            BenchError::OutOfMemory => f.write_str("out of memory"),
+            BenchError::MissingExport(name) => write!(f, "missing export '{}'", name),
+            BenchError::Unsupported(what) => write!(f, "unsupported: {}", what),
+            BenchError::WrongResult(what) => write!(f, "wrong result: {}", what),
+        }
+    }
+}
@@ -0,0 +1,584 @@
+//! Heap usage tracking that is shared by all engine integrations
+//!
+//! `TrackingHeap` wraps whatever allocator we actually use and counts every allocation that goes
+//! through it. Since the counters sit in the global allocator and not in the engines, wasmi,
+//! tinywasm, wasmtime and WAMR runs all produce numbers that can be compared directly.
+//!
+//! It also keeps a map of which parts of the heap region are in use, so it can tell how fragmented
+//! the free memory is (see [`Fragmentation`]) without asking the allocator.
+//!
+//! `CappedHeap` sits below it and can make the heap look smaller than it is, which is what the
+//! out-of-memory search (see `crate::oom`) uses to find the smallest heap an engine gets by with.
+
+use core::alloc::{GlobalAlloc, Layout};
+use core::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+
+use crate::allocator::HEAP_SIZE;
+use crate::log;
+
+/// A global allocator wrapper that records heap statistics for the inner allocator
+pub struct TrackingHeap<A> {
+    inner: A,
+    stats: HeapStats,
+}
+
+impl<A> TrackingHeap<A> {
+    pub const fn new(inner: A) -> Self {
+        Self {
+            inner,
+            stats: HeapStats::new(),
+        }
+    }
+
+    /// The wrapped allocator (e.g. to initialize it)
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    /// The statistics recorded so far
+    pub fn stats(&self) -> &HeapStats {
+        &self.stats
+    }
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for TrackingHeap<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { self.inner.alloc(layout) };
+        if ptr.is_null() {
+            self.stats.record_failure();
+        } else {
+            self.stats.record_alloc(ptr, layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { self.inner.alloc_zeroed(layout) };
+        if ptr.is_null() {
+            self.stats.record_failure();
+        } else {
+            self.stats.record_alloc(ptr, layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { self.inner.dealloc(ptr, layout) };
+        self.stats.record_free(ptr, layout.size());
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = unsafe { self.inner.realloc(ptr, layout, new_size) };
+        if new_ptr.is_null() {
+            self.stats.record_failure();
+        } else {
+            // a realloc is accounted as freeing the old block and allocating the new one
+            self.stats.record_free(ptr, layout.size());
+            self.stats.record_alloc(new_ptr, new_size);
+        }
+        new_ptr
+    }
+}
+
+/// The heap region is mapped in units of this many bytes
+///
+/// A granule is either used or free, so this relies on no two blocks sharing one. That holds for
+/// the list-based allocators, which align and pad every block to at least a word, but not for the
+/// bump allocator: it packs blocks with a smaller alignment back to back, and freeing one of them
+/// clears a granule the next one still uses. With `alloc-bump` the free memory can therefore come
+/// out too high, by less than a granule per such free.
+const GRANULE: usize = core::mem::size_of::<usize>();
+
+/// Words of the map, one bit per granule of the heap
+const MAP_WORDS: usize = HEAP_SIZE.div_ceil(GRANULE * 32);
+
+/// Counters for the heap usage
+///
+/// All counters are updated with relaxed atomics: we only care about the values themselves, not
+/// about ordering them with respect to other memory operations.
+pub struct HeapStats {
+    current: AtomicUsize,
+    peak: AtomicUsize,
+    allocations: AtomicUsize,
+    frees: AtomicUsize,
+    largest: AtomicUsize,
+    failed: AtomicUsize,
+    /// Start of the heap region, see [`HeapStats::track_region`]
+    region_start: AtomicUsize,
+    /// Size of the heap region, 0 as long as it is not known
+    region_size: AtomicUsize,
+    /// One bit per granule of the heap region, set while the granule is allocated
+    map: [AtomicU32; MAP_WORDS],
+}
+
+impl HeapStats {
+    pub const fn new() -> Self {
+        Self {
+            current: AtomicUsize::new(0),
+            peak: AtomicUsize::new(0),
+            allocations: AtomicUsize::new(0),
+            frees: AtomicUsize::new(0),
+            largest: AtomicUsize::new(0),
+            failed: AtomicUsize::new(0),
+            region_start: AtomicUsize::new(0),
+            region_size: AtomicUsize::new(0),
+            map: [const { AtomicU32::new(0) }; MAP_WORDS],
+        }
+    }
+
+    /// Maps the heap region from now on, which [`HeapStats::fragmentation`] needs
+    ///
+    /// Has to be called before the first allocation; the map covers at most `HEAP_SIZE` bytes.
+    pub fn track_region(&self, start: usize, size: usize) {
+        self.region_start.store(start, Ordering::Relaxed);
+        self.region_size
+            .store(size.min(MAP_WORDS * 32 * GRANULE), Ordering::Relaxed);
+    }
+
+    fn record_alloc(&self, ptr: *mut u8, size: usize) {
+        let current = self.current.fetch_add(size, Ordering::Relaxed) + size;
+        self.peak.fetch_max(current, Ordering::Relaxed);
+        self.allocations.fetch_add(1, Ordering::Relaxed);
+        self.largest.fetch_max(size, Ordering::Relaxed);
+        self.mark(ptr as usize, size, true);
+    }
+
+    fn record_free(&self, ptr: *mut u8, size: usize) {
+        self.current.fetch_sub(size, Ordering::Relaxed);
+        self.frees.fetch_add(1, Ordering::Relaxed);
+        self.mark(ptr as usize, size, false);
+    }
+
+    /// Sets or clears the bits of all granules the block touches
+    fn mark(&self, ptr: usize, size: usize, used: bool) {
+        let start = self.region_start.load(Ordering::Relaxed);
+        let granules = self.region_size.load(Ordering::Relaxed).div_ceil(GRANULE);
+        let Some(offset) = ptr.checked_sub(start) else {
+            return;
+        };
+        let mut granule = offset / GRANULE;
+        let end = (offset + size).div_ceil(GRANULE).min(granules);
+        while granule < end {
+            let bit = granule % 32;
+            let count = (end - granule).min(32 - bit);
+            let mask = (u32::MAX >> (32 - count)) << bit;
+            let word = &self.map[granule / 32];
+            if used {
+                word.fetch_or(mask, Ordering::Relaxed);
+            } else {
+                word.fetch_and(!mask, Ordering::Relaxed);
+            }
+            granule += count;
+        }
+    }
+
+    /// How the free memory of the heap region is split up, `None` if the region is not mapped
+    ///
+    /// Walks the whole map, so it is not meant for code that is being measured.
+    pub fn fragmentation(&self) -> Option<Fragmentation> {
+        // rounded like in `mark`, so a granule the region ends in is mapped as well
+        let granules = self.region_size.load(Ordering::Relaxed).div_ceil(GRANULE);
+        if granules == 0 {
+            return None;
+        }
+
+        let mut fragmentation = Fragmentation::default();
+        // free granules in a row so far, and the most there were in a row
+        let mut run = 0;
+        let mut longest_run = 0;
+        let mut granule = 0;
+        while granule < granules {
+            let word = self.map[granule / 32].load(Ordering::Relaxed);
+            let bit = granule % 32;
+            let count = (granules - granule).min(32 - bit);
+            // whole words are the common case, only look at single bits if they are mixed
+            if bit == 0 && count == 32 && (word == 0 || word == u32::MAX) {
+                if word == 0 {
+                    if run == 0 {
+                        fragmentation.free_blocks += 1;
+                    }
+                    run += 32;
+                    fragmentation.free += 32 * GRANULE;
+                } else {
+                    longest_run = longest_run.max(run);
+                    run = 0;
+                }
+            } else {
+                for bit in bit..bit + count {
+                    if word & (1 << bit) != 0 {
+                        longest_run = longest_run.max(run);
+                        run = 0;
+                        continue;
+                    }
+                    if run == 0 {
+                        fragmentation.free_blocks += 1;
+                    }
+                    run += 1;
+                    fragmentation.free += GRANULE;
+                }
+            }
+            granule += count;
+        }
+        fragmentation.largest_free_block = longest_run.max(run) * GRANULE;
+        Some(fragmentation)
+    }
+
+    fn record_failure(&self) {
+        self.failed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Copies the current values of all counters
+    pub fn snapshot(&self) -> HeapSnapshot {
+        HeapSnapshot {
+            current: self.current.load(Ordering::Relaxed),
+            peak: self.peak.load(Ordering::Relaxed),
+            allocations: self.allocations.load(Ordering::Relaxed),
+            frees: self.frees.load(Ordering::Relaxed),
+            largest: self.largest.load(Ordering::Relaxed),
+            failed_allocations: self.failed.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Lowers the peak to the current usage, so that the next snapshot shows the peak since now
+    pub fn reset_peak(&self) {
+        self.peak
+            .store(self.current.load(Ordering::Relaxed), Ordering::Relaxed);
+    }
+
+    /// Prints the current values of the counters, tagged with the given phase
+    pub fn log(&self, phase: &str) {
+        let snapshot = self.snapshot();
+        log::info!(
+            "heap after {}: current {}, peak {}, allocations {}, frees {}, largest {}, failed {}",
+            phase,
+            snapshot.current,
+            snapshot.peak,
+            snapshot.allocations,
+            snapshot.frees,
+            snapshot.largest,
+            snapshot.failed_allocations
+        );
+    }
+}
+
+impl Default for HeapStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The values of the heap counters at one point in time (all sizes in bytes)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(not(feature = "board-host"), derive(defmt::Format))]
+pub struct HeapSnapshot {
+    /// Bytes that are currently allocated
+    pub current: usize,
+    /// Highest value `current` ever reached
+    pub peak: usize,
+    /// Number of successful allocations
+    pub allocations: usize,
+    /// Number of frees
+    pub frees: usize,
+    /// Size of the largest single allocation
+    pub largest: usize,
+    /// Number of allocations the allocator could not satisfy
+    pub failed_allocations: usize,
+}
+
+/// How the free memory of the heap region is split up (all sizes in bytes)
+///
+/// Only what the engines hold counts as used: the headers and free lists of the allocator show up
+/// as free, and so does memory the bump allocator will never hand out again.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(not(feature = "board-host"), derive(defmt::Format))]
+pub struct Fragmentation {
+    /// Free bytes in the heap region
+    pub free: usize,
+    /// The largest allocation that could still fit, at most
+    pub largest_free_block: usize,
+    /// Number of gaps between allocated blocks (including the one at the end)
+    pub free_blocks: usize,
+}
+
+impl Fragmentation {
+    /// `1 - largest free block / free bytes` in per mille: 0 if all free memory is in one block,
+    /// close to 1000 if it is scattered over many small ones
+    pub fn permille(&self) -> usize {
+        if self.free == 0 {
+            return 0;
+        }
+        1000 - self.largest_free_block * 1000 / self.free
+    }
+}
+
+/// An allocator wrapper that refuses blocks ending beyond a limit
+///
+/// The limit is counted from the start of the heap region, so with a first-fit allocator (which
+/// hands out the lowest block that fits) a heap capped to `n` bytes behaves exactly like a heap of
+/// `n` bytes, fragmentation included.
+pub struct CappedHeap<A> {
+    inner: A,
+    limit: HeapLimit,
+}
+
+impl<A> CappedHeap<A> {
+    pub const fn new(inner: A) -> Self {
+        Self {
+            inner,
+            limit: HeapLimit::new(),
+        }
+    }
+
+    /// The wrapped allocator (e.g. to initialize it)
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    pub fn limit(&self) -> &HeapLimit {
+        &self.limit
+    }
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for CappedHeap<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { self.inner.alloc(layout) };
+        if !ptr.is_null() && !self.limit.allows(ptr as usize, layout.size()) {
+            unsafe { self.inner.dealloc(ptr, layout) };
+            return core::ptr::null_mut();
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { self.inner.dealloc(ptr, layout) };
+    }
+
+    // `alloc_zeroed` and `realloc` keep their default implementations, which go through `alloc`
+}
+
+/// The end of the usable heap, as used by [`CappedHeap`]
+pub struct HeapLimit {
+    start: AtomicUsize,
+    limit: AtomicUsize,
+}
+
+impl HeapLimit {
+    pub const fn new() -> Self {
+        Self {
+            start: AtomicUsize::new(0),
+            limit: AtomicUsize::new(usize::MAX),
+        }
+    }
+
+    /// Where the heap region starts; the limit is counted from here
+    pub fn set_start(&self, start: usize) {
+        self.start.store(start, Ordering::Relaxed);
+    }
+
+    /// Lets the heap use only its first `bytes` bytes from now on
+    pub fn cap(&self, bytes: usize) {
+        self.limit.store(bytes, Ordering::Relaxed);
+    }
+
+    /// Lets the heap use all of its region again
+    pub fn remove(&self) {
+        self.limit.store(usize::MAX, Ordering::Relaxed);
+    }
+
+    fn allows(&self, ptr: usize, size: usize) -> bool {
+        let used = (ptr + size).saturating_sub(self.start.load(Ordering::Relaxed));
+        used <= self.limit.load(Ordering::Relaxed)
+    }
+}
+
+impl Default for HeapLimit {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    extern crate std;
+    use std::alloc::System;
+
+    /// Where the mapped region of the tests starts; nothing is ever read or written there
+    const START: usize = 0x1000;
+
+    fn mapped(granules: usize) -> HeapStats {
+        let stats = HeapStats::new();
+        stats.track_region(START, granules * GRANULE);
+        stats
+    }
+
+    /// Records an allocation of the granules `from..to` of the region
+    fn alloc(stats: &HeapStats, from: usize, to: usize) {
+        stats.record_alloc((START + from * GRANULE) as *mut u8, (to - from) * GRANULE);
+    }
+
+    fn free(stats: &HeapStats, from: usize, to: usize) {
+        stats.record_free((START + from * GRANULE) as *mut u8, (to - from) * GRANULE);
+    }
+
+    fn fragmentation(free: usize, largest_free_block: usize, free_blocks: usize) -> Fragmentation {
+        Fragmentation {
+            free: free * GRANULE,
+            largest_free_block: largest_free_block * GRANULE,
+            free_blocks,
+        }
+    }
+
+    #[test]
+    fn unmapped_region_has_no_fragmentation() {
+        assert_eq!(HeapStats::new().fragmentation(), None);
+    }
+
+    #[test]
+    fn empty_region_is_one_free_block() {
+        let stats = mapped(96);
+        assert_eq!(stats.fragmentation(), Some(fragmentation(96, 96, 1)));
+        assert_eq!(stats.fragmentation().unwrap().permille(), 0);
+    }
+
+    #[test]
+    fn gaps_between_blocks() {
+        let stats = mapped(96);
+        alloc(&stats, 0, 4);
+        alloc(&stats, 10, 12);
+        alloc(&stats, 40, 64);
+        // free: 4..10, 12..40 and 64..96
+        assert_eq!(stats.fragmentation(), Some(fragmentation(66, 32, 3)));
+
+        free(&stats, 10, 12);
+        // free: 4..40 and 64..96
+        assert_eq!(stats.fragmentation(), Some(fragmentation(68, 36, 2)));
+    }
+
+    #[test]
+    fn run_ending_mid_word_counts() {
+        let stats = mapped(64);
+        alloc(&stats, 0, 3);
+        alloc(&stats, 20, 64);
+        // the only gap, 3..20, ends within the first word
+        assert_eq!(stats.fragmentation(), Some(fragmentation(17, 17, 1)));
+    }
+
+    #[test]
+    fn run_across_words() {
+        let stats = mapped(128);
+        alloc(&stats, 0, 20);
+        alloc(&stats, 90, 100);
+        alloc(&stats, 120, 128);
+        // 20..90 spans a partial, a whole and another partial word; 100..120 is shorter
+        assert_eq!(stats.fragmentation(), Some(fragmentation(90, 70, 2)));
+    }
+
+    #[test]
+    fn partial_granules_are_used() {
+        let stats = HeapStats::new();
+        stats.track_region(START, 10 * GRANULE + 1);
+        // one byte in the middle of granule 2, and the byte the region ends with
+        stats.record_alloc((START + 2 * GRANULE + 1) as *mut u8, 1);
+        stats.record_alloc((START + 10 * GRANULE) as *mut u8, 1);
+        assert_eq!(stats.fragmentation(), Some(fragmentation(9, 7, 2)));
+    }
+
+    fn layout(size: usize) -> Layout {
+        Layout::from_size_align(size, 8).unwrap()
+    }
+
+    #[test]
+    fn tracks_current_and_peak() {
+        let heap = TrackingHeap::new(System);
+        unsafe {
+            let a = heap.alloc(layout(100));
+            let b = heap.alloc_zeroed(layout(50));
+            let before = heap.stats().snapshot();
+            heap.dealloc(a, layout(100));
+            let after = heap.stats().snapshot();
+            heap.dealloc(b, layout(50));
+
+            assert_eq!(
+                after,
+                HeapSnapshot {
+                    current: 50,
+                    peak: 150,
+                    allocations: 2,
+                    frees: 1,
+                    largest: 100,
+                    failed_allocations: 0,
+                }
+            );
+            // the delta of a phase, as `crate::phase` computes it
+            assert_eq!(after.current as isize - before.current as isize, -100);
+        }
+        assert_eq!(heap.stats().snapshot().current, 0);
+    }
+
+    #[test]
+    fn reset_peak_starts_from_current() {
+        let heap = TrackingHeap::new(System);
+        unsafe {
+            let a = heap.alloc(layout(200));
+            heap.dealloc(a, layout(200));
+            let b = heap.alloc(layout(30));
+            heap.stats().reset_peak();
+            assert_eq!(heap.stats().snapshot().peak, 30);
+
+            let c = heap.alloc(layout(20));
+            assert_eq!(heap.stats().snapshot().peak, 50);
+            heap.dealloc(c, layout(20));
+            heap.dealloc(b, layout(30));
+        }
+        assert_eq!(heap.stats().snapshot().peak, 50);
+    }
+
+    #[test]
+    fn realloc_frees_the_old_block() {
+        let heap = TrackingHeap::new(System);
+        unsafe {
+            let a = heap.alloc(layout(64));
+            let a = heap.realloc(a, layout(64), 256);
+            assert!(!a.is_null());
+            let snapshot = heap.stats().snapshot();
+            assert_eq!(snapshot.current, 256);
+            // the old block is gone before the new one counts, whether it moved or not
+            assert_eq!(snapshot.peak, 256);
+            assert_eq!(snapshot.allocations, 2);
+            assert_eq!(snapshot.frees, 1);
+            assert_eq!(snapshot.largest, 256);
+            heap.dealloc(a, layout(256));
+        }
+        assert_eq!(heap.stats().snapshot().current, 0);
+    }
+
+    #[test]
+    fn capped_heap_refuses_blocks_beyond_the_limit() {
+        let heap = TrackingHeap::new(CappedHeap::new(System));
+        let limit = heap.inner().limit();
+        unsafe {
+            let a = heap.alloc(layout(64));
+            assert!(!a.is_null());
+
+            // counted from the start of a, there is room for a itself but not for more
+            limit.set_start(a as usize);
+            limit.cap(64);
+            let b = heap.alloc(layout(64));
+            assert!(b.is_null());
+            assert!(heap.alloc_zeroed(layout(64)).is_null());
+            assert!(heap.realloc(a, layout(64), 4096).is_null());
+            assert_eq!(heap.stats().snapshot().failed_allocations, 3);
+
+            // a refused block is not counted and a is still there
+            assert_eq!(heap.stats().snapshot().current, 64);
+            limit.remove();
+            let b = heap.alloc(layout(64));
+            assert!(!b.is_null());
+            heap.dealloc(b, layout(64));
+            heap.dealloc(a, layout(64));
+        }
+        assert_eq!(heap.stats().snapshot().current, 0);
+    }
+}
@@ -0,0 +1,241 @@
+//! The state the host functions of an engine share
+//!
+//! Each engine keeps one [`HostState`] next to its instance: wasmi and wasmtime as the data of
+//! their store, tinywasm in the closures of its imports (its function context has no user data),
+//! and WAMR as the attachment of its native symbols. The imports use it to reach the guest memory,
+//! to decide where the messages go, and to count what they did.
+//!
+//! Arguments of the guest are never trusted: a pointer and length that do not fit the guest memory
+//! make the import trap (see [`HostError`]) instead of panicking the whole firmware.
+
+use crate::log;
+
+/// What `logging.log` does with the message of the guest
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(not(feature = "board-host"), derive(defmt::Format))]
+pub enum LogSink {
+    /// Logs the message (the default)
+    Log,
+    /// Only reads the message, which leaves out the cost of defmt and RTT (or stdout)
+    Discard,
+}
+
+/// Limits the host puts on the guest
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(not(feature = "board-host"), derive(defmt::Format))]
+pub struct Limits {
+    /// Fuel the guest starts with, on the engines that meter it (wasmi, wasmtime);
+    /// `None` runs the guest unmetered
+    pub fuel: Option<u64>,
+    /// Longest message `logging.log` accepts
+    pub max_message_len: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            fuel: None,
+            max_message_len: 256,
+        }
+    }
+}
+
+/// Name of the logging import in the error reports
+pub const LOG_IMPORT: &str = "logging.log";
+
+/// Why a host function refused the arguments of the guest; the engine turns it into a trap
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(not(feature = "board-host"), derive(defmt::Format))]
+pub enum HostError {
+    /// The module does not export the memory the arguments point into
+    NoMemory,
+    /// `ptr..ptr + len` is not inside the guest memory (or does not even fit into 32 bits)
+    OutOfBounds { ptr: u32, len: u32 },
+}
+
+impl HostError {
+    /// The message the guest traps with
+    pub fn message(&self) -> &'static str {
+        match self {
+            HostError::NoMemory => "module does not export its memory",
+            HostError::OutOfBounds { .. } => "message is outside of the guest memory",
+        }
+    }
+}
+
+/// The bytes `ptr..ptr + len` of the guest memory, if all of them are inside it
+pub fn guest_bytes(memory: &[u8], ptr: u32, len: u32) -> Result<&[u8], HostError> {
+    let out_of_bounds = HostError::OutOfBounds { ptr, len };
+    let end = ptr.checked_add(len).ok_or(out_of_bounds)?;
+    memory.get(ptr as usize..end as usize).ok_or(out_of_bounds)
+}
+
+/// What the host functions did so far
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(not(feature = "board-host"), derive(defmt::Format))]
+pub struct HostStats {
+    /// Calls of `logging.log`
+    pub log_calls: u32,
+    /// Bytes `logging.log` read from the guest memory
+    pub logged_bytes: usize,
+    /// Messages that were dropped for exceeding [`Limits::max_message_len`]
+    pub rejected_messages: u32,
+    /// Calls that trapped because of their arguments
+    pub traps: u32,
+}
+
+/// The host state of one engine; `M` is how the engine refers to the guest memory
+#[derive(Debug)]
+pub struct HostState<M> {
+    /// The engine, for the error reports
+    pub engine: &'static str,
+    /// The exported memory of the guest, resolved once the module is instantiated
+    pub memory: Option<M>,
+    pub sink: LogSink,
+    pub limits: Limits,
+    pub stats: HostStats,
+}
+
+impl<M> HostState<M> {
+    pub fn new(engine: &'static str, limits: Limits) -> Self {
+        Self {
+            engine,
+            memory: None,
+            sink: LogSink::Log,
+            limits,
+            stats: HostStats::default(),
+        }
+    }
+
+    /// The `logging.log` import for engines that hand out the guest memory as a slice
+    ///
+    /// Returns the message to trap with if `ptr` and `len` do not fit the memory.
+    pub fn log_from(
+        &mut self,
+        memory: Option<&[u8]>,
+        ptr: u32,
+        len: u32,
+    ) -> Result<(), &'static str> {
+        let message = memory
+            .ok_or(HostError::NoMemory)
+            .and_then(|memory| guest_bytes(memory, ptr, len));
+        match message {
+            Ok(message) => {
+                self.log(message);
+                Ok(())
+            }
+            Err(error) => Err(self.trap(LOG_IMPORT, error)),
+        }
+    }
+
+    /// Reports that `import` refused its arguments; returns the message to trap with
+    pub fn trap(&mut self, import: &str, error: HostError) -> &'static str {
+        self.stats.traps += 1;
+        match error {
+            HostError::NoMemory => {
+                log::error!("{} | {} trapped: {}", self.engine, import, error.message())
+            }
+            HostError::OutOfBounds { ptr, len } => log::error!(
+                "{} | {} trapped: {} (ptr {}, len {})",
+                self.engine,
+                import,
+                error.message(),
+                ptr,
+                len
+            ),
+        }
+        error.message()
+    }
+
+    /// What the `logging.log` import does with the bytes the guest handed over
+    pub fn log(&mut self, message: &[u8]) {
+        self.stats.log_calls += 1;
+        self.stats.logged_bytes += message.len();
+
+        if message.len() > self.limits.max_message_len {
+            self.stats.rejected_messages += 1;
+            log::warn!(
+                "module logged {} bytes, dropping the message",
+                message.len()
+            );
+            return;
+        }
+
+        match self.sink {
+            LogSink::Log => match core::str::from_utf8(message) {
+                Ok(message) => log::info!("module log: {}", message),
+                Err(_) => log::error!("module logged using an invalid string"),
+            },
+            // the engine already handed us the guest memory, make sure it is not optimized away
+            LogSink::Discard => {
+                core::hint::black_box(message);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MEMORY: &[u8] = &[1, 2, 3, 4, 5, 6, 7, 8];
+
+    #[test]
+    fn bytes_inside_the_memory() {
+        assert_eq!(guest_bytes(MEMORY, 2, 3), Ok(&MEMORY[2..5]));
+        assert_eq!(guest_bytes(MEMORY, 0, 0), Ok(&[][..]));
+    }
+
+    #[test]
+    fn message_up_to_the_last_byte() {
+        assert_eq!(guest_bytes(MEMORY, 0, 8), Ok(MEMORY));
+        assert_eq!(guest_bytes(MEMORY, 5, 3), Ok(&MEMORY[5..]));
+        // an empty message right at the end is still inside
+        assert_eq!(guest_bytes(MEMORY, 8, 0), Ok(&[][..]));
+    }
+
+    #[test]
+    fn message_past_the_end() {
+        assert_eq!(
+            guest_bytes(MEMORY, 5, 4),
+            Err(HostError::OutOfBounds { ptr: 5, len: 4 })
+        );
+        assert_eq!(
+            guest_bytes(MEMORY, 9, 0),
+            Err(HostError::OutOfBounds { ptr: 9, len: 0 })
+        );
+    }
+
+    #[test]
+    fn pointer_past_the_end() {
+        assert_eq!(
+            guest_bytes(MEMORY, 100, 1),
+            Err(HostError::OutOfBounds { ptr: 100, len: 1 })
+        );
+    }
+
+    #[test]
+    fn overflowing_end() {
+        assert_eq!(
+            guest_bytes(MEMORY, u32::MAX, 2),
+            Err(HostError::OutOfBounds {
+                ptr: u32::MAX,
+                len: 2
+            })
+        );
+    }
+
+    #[test]
+    fn negative_length() {
+        // the guest passes an `i32`, so -1 arrives as the largest length there is
+        let len = -1i32 as u32;
+        assert_eq!(
+            guest_bytes(MEMORY, 0, len),
+            Err(HostError::OutOfBounds { ptr: 0, len })
+        );
+        assert_eq!(
+            guest_bytes(MEMORY, 4, len),
+            Err(HostError::OutOfBounds { ptr: 4, len })
+        );
+    }
+}
@@ -0,0 +1,288 @@
+//! What a single call of the `logging.log` import costs
+//!
+//! Every engine implements the import its own way (wasmi through `Linker::func_wrap`, tinywasm
+//! through `Extern::typed_func`, wasmtime through `Func::wrap`, WAMR through a native symbol), so
+//! the cost of crossing from the guest to the host differs. The `host_calls` export of the
+//! benchmark module does nothing but call the import, and we divide what we measured by the number
+//! of calls.
+//!
+//! With the [`LogSink::Discard`] sink the message is read but not logged, which leaves out the cost
+//! of defmt and RTT (or stdout); [`LogSink::Log`] shows what a real log call costs.
+//!
+//! [`check_hostile_arguments`] makes sure the import refuses pointers and lengths that do not fit
+//! the guest memory by trapping, instead of reading outside of it or panicking.
+
+use crate::engine::{Value, WasmEngine};
+use crate::error::BenchError;
+use crate::host::LogSink;
+use crate::log;
+use crate::phase::{self, Measurement, PhaseReport};
+use crate::results::{self, or_unknown, ResultRecord};
+use crate::timing;
+
+/// One run of the `host_calls` export
+struct HostCallRun {
+    /// Used as the phase in the results
+    name: &'static str,
+    sink: LogSink,
+    /// Kept low for the logging sink, every call is a log line
+    calls: i32,
+}
+
+const RUNS: &[HostCallRun] = &[
+    HostCallRun {
+        name: "host_call_discard",
+        sink: LogSink::Discard,
+        calls: 1000,
+    },
+    HostCallRun {
+        name: "host_call_log",
+        sink: LogSink::Log,
+        calls: 10,
+    },
+];
+
+/// Measures the cost per host call with every sink; returns the number of runs that failed
+pub fn run_all<E: WasmEngine>(
+    engine: &mut E,
+    instance: &mut E::Instance,
+    report: &PhaseReport,
+) -> usize {
+    let mut failures = 0;
+    for run in RUNS {
+        engine.set_log_sink(run.sink);
+        let received_before = engine.host_stats().logged_bytes;
+        let (result, measurement) = phase::measure(report.heap(), || {
+            engine.call(instance, "host_calls", &[Value::I32(run.calls)])
+        });
+        let received = engine.host_stats().logged_bytes - received_before;
+        engine.set_log_sink(LogSink::Log);
+
+        match result {
+            Ok(Some(Value::I32(calls))) if calls == run.calls => {
+                log_measurement(report, run, &measurement, received);
+            }
+            Ok(_) => {
+                failures += 1;
+                log::error!("{} | {} did not make all calls", report.engine(), run.name);
+            }
+            Err(err) => {
+                failures += 1;
+                log::error!(
+                    "{} | {} failed: {}",
+                    report.engine(),
+                    run.name,
+                    measurement.classify(err)
+                );
+            }
+        }
+        emit_measurement(report, run, &measurement);
+    }
+    failures
+}
+
+/// The cases of the `log_out_of_bounds` export
+const HOSTILE_ARGUMENTS: &[(i32, &str)] = &[
+    (0, "pointer past the memory"),
+    (1, "message past the memory"),
+    (2, "overflowing end"),
+    (3, "negative length"),
+];
+
+/// Calls the import with arguments it has to refuse; returns the number of calls that did not trap
+///
+/// Only a trap the import raised itself counts, or any trap if the runtime checks the arguments
+/// before the import runs ([`WasmEngine::CHECKS_IMPORT_ARGUMENTS`]): any other error (a missing
+/// export, running out of memory) fails the check as well.
+pub fn check_hostile_arguments<E: WasmEngine>(
+    engine: &mut E,
+    instance: &mut E::Instance,
+    report: &PhaseReport,
+) -> usize {
+    let mut failures = 0;
+    for &(case, description) in HOSTILE_ARGUMENTS {
+        let traps_before = engine.host_stats().traps;
+        let result = engine.call(instance, "log_out_of_bounds", &[Value::I32(case)]);
+        let import_trapped = E::CHECKS_IMPORT_ARGUMENTS || engine.host_stats().traps > traps_before;
+        match result {
+            Err(BenchError::Trap(_)) if import_trapped => {
+                log::info!("{} | {}: trapped", report.engine(), description)
+            }
+            Err(err) => {
+                failures += 1;
+                log::error!(
+                    "{} | {}: failed without the import trapping: {}",
+                    report.engine(),
+                    description,
+                    err
+                );
+            }
+            Ok(_) => {
+                failures += 1;
+                log::error!(
+                    "{} | {}: the import did not trap",
+                    report.engine(),
+                    description
+                );
+            }
+        }
+    }
+    failures
+}
+
+fn log_measurement(
+    report: &PhaseReport,
+    run: &HostCallRun,
+    measurement: &Measurement,
+    received: usize,
+) {
+    let calls = run.calls as u64;
+    log::info!(
+        "{} | {} | {} calls | {} {} per call | {} bytes read per call | heap delta {} | heap peak {} | stack peak {}",
+        report.engine(),
+        run.name,
+        run.calls,
+        or_unknown(measurement.cycles.map(|cycles| cycles / calls)),
+        timing::UNIT,
+        received as u64 / calls,
+        measurement.heap_delta,
+        measurement.heap_peak,
+        or_unknown(measurement.stack_peak)
+    );
+}
+
+fn emit_measurement(report: &PhaseReport, run: &HostCallRun, measurement: &Measurement) {
+    ResultRecord {
+        engine: report.engine(),
+        module: report.module(),
+        phase: run.name,
+        heap_delta: measurement.heap_delta,
+        heap_peak: measurement.heap_peak,
+        heap_after: measurement.heap_after.current,
+        stack_peak: measurement.stack_peak,
+        flash_size: results::flash_size(),
+        duration_us: measurement.duration_us,
+        cycles: measurement.cycles,
+        fragmentation: measurement.fragmentation,
+    }
+    .emit();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heap::HeapStats;
+    use crate::host::HostStats;
+    use crate::registry::Format;
+
+    /// Answers every call with `result`; `RUNTIME_CHECKS` stands for
+    /// [`WasmEngine::CHECKS_IMPORT_ARGUMENTS`]
+    struct FakeEngine<const RUNTIME_CHECKS: bool> {
+        result: fn() -> Result<Option<Value>, BenchError>,
+        /// Whether the host function runs and records a trap
+        host_traps: bool,
+        stats: HostStats,
+    }
+
+    impl<const RUNTIME_CHECKS: bool> WasmEngine for FakeEngine<RUNTIME_CHECKS> {
+        const NAME: &'static str = "fake";
+        const FORMAT: Format = Format::Wasm;
+        const CHECKS_IMPORT_ARGUMENTS: bool = RUNTIME_CHECKS;
+
+        type Module = ();
+        type Instance = ();
+
+        fn new() -> Result<Self, BenchError> {
+            Err(BenchError::Unsupported("fake engine"))
+        }
+
+        fn load_module(&mut self, _bytes: &'static [u8]) -> Result<(), BenchError> {
+            Ok(())
+        }
+
+        fn link_host_functions(&mut self) -> Result<(), BenchError> {
+            Ok(())
+        }
+
+        fn instantiate(&mut self, _module: &mut ()) -> Result<(), BenchError> {
+            Ok(())
+        }
+
+        fn call(
+            &mut self,
+            _instance: &mut (),
+            _name: &str,
+            _args: &[Value],
+        ) -> Result<Option<Value>, BenchError> {
+            if self.host_traps {
+                self.stats.traps += 1;
+            }
+            (self.result)()
+        }
+
+        fn memory_size(&mut self, _instance: &()) -> Option<usize> {
+            None
+        }
+
+        fn set_log_sink(&mut self, _sink: LogSink) {}
+
+        fn host_stats(&mut self) -> HostStats {
+            self.stats
+        }
+    }
+
+    fn trap() -> Result<Option<Value>, BenchError> {
+        Err(BenchError::Trap("out of bounds memory access".into()))
+    }
+
+    fn returned() -> Result<Option<Value>, BenchError> {
+        Ok(None)
+    }
+
+    fn missing_export() -> Result<Option<Value>, BenchError> {
+        Err(BenchError::MissingExport("log_out_of_bounds".into()))
+    }
+
+    fn failures<const RUNTIME_CHECKS: bool>(
+        result: fn() -> Result<Option<Value>, BenchError>,
+        host_traps: bool,
+    ) -> usize {
+        let heap = HeapStats::new();
+        let report = PhaseReport::new("fake", "benchmark_module", &heap);
+        let mut engine = FakeEngine::<RUNTIME_CHECKS> {
+            result,
+            host_traps,
+            stats: HostStats::default(),
+        };
+        check_hostile_arguments(&mut engine, &mut (), &report)
+    }
+
+    #[test]
+    fn trap_of_the_import_passes() {
+        assert_eq!(failures::<false>(trap, true), 0);
+    }
+
+    #[test]
+    fn trap_of_the_runtime_passes_if_it_checks_the_arguments() {
+        assert_eq!(failures::<true>(trap, false), 0);
+    }
+
+    #[test]
+    fn trap_without_the_import_fails_if_the_import_checks_the_arguments() {
+        assert_eq!(failures::<false>(trap, false), HOSTILE_ARGUMENTS.len());
+    }
+
+    #[test]
+    fn returning_or_other_errors_fail() {
+        assert_eq!(failures::<false>(returned, false), HOSTILE_ARGUMENTS.len());
+        assert_eq!(failures::<true>(returned, false), HOSTILE_ARGUMENTS.len());
+        assert_eq!(
+            failures::<false>(missing_export, true),
+            HOSTILE_ARGUMENTS.len()
+        );
+        assert_eq!(
+            failures::<true>(missing_export, false),
+            HOSTILE_ARGUMENTS.len()
+        );
+    }
+}
@@ -1,7 +1,29 @@
-#![no_std]
+#![cfg_attr(not(feature = "board-host"), no_std)]
 #![feature(c_variadic)]
 #![feature(str_as_str)]
 
+#[cfg(all(feature = "board-host", feature = "engine-wamr"))]
+compile_error!("WAMR runs a Thumb AOT module and can not be used with the host build");
+
+pub mod allocator;
+pub mod engine;
+pub mod error;
+pub mod heap;
+pub mod host;
+pub mod hostcall;
+mod log;
+pub mod oom;
+pub mod phase;
+#[cfg(feature = "board-qemu")]
+mod qemu;
+pub mod registry;
+pub mod results;
+pub mod source;
+pub mod stack;
+pub mod suite;
+pub mod timing;
+pub mod workload;
+
 #[cfg(feature = "engine-wasmtime")]
 pub mod wasmtime;
 
@@ -0,0 +1,55 @@
+//! Logging that works on the boards (defmt over RTT) as well as on the host (stdout)
+//!
+//! The format strings have to be understood by both `defmt` and `core::fmt`, so stick to plain `{}`
+//! placeholders (and the display hints both support, like `{:08x}`) and values that implement both
+//! `defmt::Format` and `core::fmt::Display`.
+
+// not every level is used in every feature combination
+#![allow(unused_macros)]
+
+#[cfg(not(feature = "board-host"))]
+macro_rules! log_info {
+    ($($arg:tt)*) => { defmt::info!($($arg)*) };
+}
+
+#[cfg(not(feature = "board-host"))]
+macro_rules! log_warn {
+    ($($arg:tt)*) => { defmt::warn!($($arg)*) };
+}
+
+#[cfg(not(feature = "board-host"))]
+macro_rules! log_error {
+    ($($arg:tt)*) => { defmt::error!($($arg)*) };
+}
+
+/// Prints without a log level (e.g. for the machine-readable results)
+#[cfg(not(feature = "board-host"))]
+macro_rules! log_println {
+    ($($arg:tt)*) => { defmt::println!($($arg)*) };
+}
+
+#[cfg(feature = "board-host")]
+macro_rules! log_info {
+    ($($arg:tt)*) => { std::println!("INFO  {}", format_args!($($arg)*)) };
+}
+
+#[cfg(feature = "board-host")]
+macro_rules! log_warn {
+    ($($arg:tt)*) => { std::println!("WARN  {}", format_args!($($arg)*)) };
+}
+
+#[cfg(feature = "board-host")]
+macro_rules! log_error {
+    ($($arg:tt)*) => { std::println!("ERROR {}", format_args!($($arg)*)) };
+}
+
+/// Prints without a log level (e.g. for the machine-readable results)
+#[cfg(feature = "board-host")]
+macro_rules! log_println {
+    ($($arg:tt)*) => { std::println!($($arg)*) };
+}
+
+// exported under their short names; `warn` can not be defined directly, it clashes with the
+// built-in attribute
+#[allow(unused_imports)]
+pub(crate) use {log_error as error, log_info as info, log_println as println, log_warn as warn};
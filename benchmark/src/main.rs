@@ -1,60 +1,76 @@
-#![no_std]
-#![no_main]
+#![cfg_attr(not(feature = "board-host"), no_std)]
+#![cfg_attr(not(feature = "board-host"), no_main)]
 
 extern crate alloc;
 
+#[cfg(feature = "nrf")]
 use defmt_rtt as _;
+#[cfg(feature = "board-qemu")]
+use defmt_semihosting as _;
+#[cfg(feature = "nrf")]
 use embassy_nrf as _;
 use embassy_time as _;
 
-#[cfg(feature = "engine-tinywasm")]
-use memory_benchmark::tiny;
-#[cfg(feature = "engine-wamr")]
-use memory_benchmark::wamr;
-#[cfg(feature = "engine-wasmi")]
-use memory_benchmark::wasmi;
-#[cfg(feature = "engine-wasmtime")]
-use memory_benchmark::wasmtime;
+// the out-of-memory search brings its own panic handler, which resets instead of halting
+#[cfg(all(feature = "mcu", not(feature = "oom-search")))]
 use panic_probe as _;
 
 use core::{mem::MaybeUninit, ptr::addr_of_mut};
 
+#[cfg(feature = "mcu")]
+use embassy_executor::Executor;
 use embassy_executor::Spawner;
 
-pub const HEAP_SIZE: usize = 200_000;
-
-use embedded_alloc::Heap;
+use memory_benchmark::allocator::{Backend, HEAP_SIZE};
+use memory_benchmark::heap::{CappedHeap, TrackingHeap};
+#[cfg(feature = "oom-search")]
+use memory_benchmark::oom;
+#[cfg(not(feature = "oom-search"))]
+use memory_benchmark::suite;
+use memory_benchmark::stack;
 #[global_allocator]
-static HEAP: Heap = Heap::empty();
+static HEAP: TrackingHeap<CappedHeap<Backend>> =
+    TrackingHeap::new(CappedHeap::new(Backend::empty()));
 
 /// Initializes the allocator
 fn init_allocator() {
     static mut HEAP_MEM: [MaybeUninit<u8>; HEAP_SIZE] = [MaybeUninit::uninit(); HEAP_SIZE];
-    unsafe { HEAP.init(addr_of_mut!(HEAP_MEM) as usize, HEAP_SIZE) }
+    let start = addr_of_mut!(HEAP_MEM) as usize;
+    HEAP.inner().limit().set_start(start);
+    HEAP.stats().track_region(start, HEAP_SIZE);
+    unsafe { HEAP.inner().inner().init(start, HEAP_SIZE) }
 }
 
-#[embassy_executor::main]
-async fn main(spawner: Spawner) {
-    init_allocator();
+/// On the host, the std runtime allocates before `main` runs, so the heap has to be ready earlier:
+/// we initialize it from `.init_array`, which the loader runs before handing control to std
+#[cfg(feature = "board-host")]
+#[used]
+#[link_section = ".init_array"]
+static INIT_ALLOCATOR: extern "C" fn() = {
+    extern "C" fn init() {
+        init_allocator();
+    }
+    init
+};
 
-    #[cfg(feature = "engine-tinywasm")]
-    spawner
-        .spawn(tiny::wasm_task())
-        .expect("failed to spawn wasm task");
-    #[cfg(feature = "engine-wasmi")]
-    spawner
-        .spawn(wasmi::wasm_task())
-        .expect("failed to spawn wasm task");
+/// The entry point on the boards: what `#[embassy_executor::main]` expands to, except that the
+/// task arena is cleared before the first task is spawned into it (see `stack::task_arena`)
+#[cfg(feature = "mcu")]
+#[cortex_m_rt::entry]
+fn entry() -> ! {
+    static mut EXECUTOR: MaybeUninit<Executor> = MaybeUninit::uninit();
 
-    #[cfg(feature = "engine-wasmtime")]
-    spawner
-        .spawn(wasmtime::wasm_task())
-        .expect("failed to spawn wasm task");
+    unsafe { stack::clear_task_arena() };
+    let executor = EXECUTOR.write(Executor::new());
+    executor.run(|spawner| spawner.must_spawn(main(spawner)))
+}
 
-    #[cfg(feature = "engine-wamr")]
-    spawner
-        .spawn(wamr::wasm_task())
-        .expect("failed to spawn wasm task");
+#[cfg_attr(feature = "board-host", embassy_executor::main)]
+#[cfg_attr(feature = "mcu", embassy_executor::task)]
+async fn main(spawner: Spawner) {
+    stack::paint_main_stack();
+    #[cfg(not(feature = "board-host"))]
+    init_allocator();
 
     #[cfg(not(any(
         feature = "engine-tinywasm",
@@ -62,5 +78,19 @@ async fn main(spawner: Spawner) {
         feature = "engine-wasmtime",
         feature = "engine-wamr"
     )))]
-    unimplemented!("one of the engines has to be active")
+    unimplemented!("one of the engines has to be active");
+
+    // the enabled engines run one after another, see `suite`
+    #[cfg(not(feature = "oom-search"))]
+    #[allow(unreachable_code)]
+    spawner
+        .spawn(suite::benchmark_task(HEAP.stats()))
+        .expect("failed to spawn benchmark task");
+
+    // instead of the benchmark, search the smallest heap of each engine, see `oom`
+    #[cfg(feature = "oom-search")]
+    #[allow(unreachable_code)]
+    spawner
+        .spawn(oom::search_task(HEAP.inner().limit(), HEAP_SIZE))
+        .expect("failed to spawn out-of-memory search task");
 }
@@ -0,0 +1,253 @@
+//! Searches the smallest heap each engine gets by with (`oom-search` feature)
+//!
+//! `HEAP_SIZE` says how much heap the firmware has, not how much an engine needs. The search caps
+//! the heap (see [`CappedHeap`](crate::heap::CappedHeap)) and bisects the cap until it knows the
+//! smallest heap with which an engine still goes through its whole lifecycle, and the smallest one
+//! with which it still runs each workload.
+//!
+//! Every probe runs on a fresh heap, and most engines do not survive a failed allocation (Rust
+//! turns it into a panic), so a probe is a run of its own: on the host a child process, on the
+//! boards one boot. There, the state of the search lives in RAM that is not initialized at boot,
+//! and the panic handler resets the MCU instead of halting it; a probe that did not finish when
+//! the MCU comes back up failed.
+
+use crate::allocator;
+use crate::engine::{self, Value, WasmEngine};
+use crate::error::BenchError;
+use crate::heap::HeapLimit;
+use crate::host::LogSink;
+use crate::log;
+use crate::registry;
+use crate::results::or_unknown;
+use crate::suite::{ENGINES, MAX_ENGINES};
+use crate::workload::WORKLOADS;
+
+/// Runs one engine up to and including a target, with whatever heap it is given
+pub type Probe = fn(usize) -> Result<(), BenchError>;
+
+/// Targets of every engine: the whole lifecycle, then each workload on its own
+const TARGETS_PER_ENGINE: usize = 1 + WORKLOADS.len();
+
+const MAX_TARGETS: usize = MAX_ENGINES * TARGETS_PER_ENGINE;
+
+/// The search stops once it knows the smallest heap to within this many bytes
+const GRANULARITY: u32 = 64;
+
+/// Marks a valid search state in the RAM that survives a reset
+const MAGIC: u32 = 0x4f4f_4d53;
+
+/// Stands for "not even the whole heap is enough"
+const DOES_NOT_FIT: u32 = u32::MAX;
+
+#[embassy_executor::task]
+pub async fn search_task(limit: &'static HeapLimit, heap_size: usize) {
+    search(limit, heap_size);
+}
+
+/// Goes through the lifecycle of `E` (the target `0`) or instantiates it and runs the workload
+/// `target - 1`, checking its checksum; always with the benchmark module, which the workloads
+/// belong to
+pub fn probe<E: WasmEngine>(target: usize) -> Result<(), BenchError> {
+    let guest = registry::find(registry::BENCHMARK_MODULE).ok_or(BenchError::Unsupported(
+        "the benchmark module is not compiled in",
+    ))?;
+    let bytes = engine::artifact::<E>(guest)?;
+    let mut engine = E::new()?;
+    // thousands of probes would otherwise log the module messages
+    engine.set_log_sink(LogSink::Discard);
+    if E::VALIDATES {
+        engine.validate_module(bytes)?;
+    }
+    let mut module = engine.load_module(bytes)?;
+    engine.link_host_functions()?;
+    let mut instance = engine.instantiate(&mut module)?;
+
+    match target.checked_sub(1).map(|index| &WORKLOADS[index]) {
+        None => {
+            engine::call_run(&mut engine, &mut instance)?;
+            engine::call_run(&mut engine, &mut instance)?;
+        }
+        Some(workload) => {
+            let result = engine.call(&mut instance, workload.name, &[Value::I32(workload.arg)])?;
+            if result != Some(Value::I32(workload.expected)) {
+                return Err(BenchError::WrongResult("wrong checksum"));
+            }
+        }
+    }
+
+    drop(instance);
+    drop(module);
+    drop(engine);
+    Ok(())
+}
+
+/// A bisection over all targets; only `u32`s, so it can live in uninitialized RAM
+#[repr(C)]
+struct Search {
+    magic: u32,
+    heap_size: u32,
+    /// Index into all targets of all engines
+    target: u32,
+    /// Largest cap known to fail (0 is assumed to)
+    low: u32,
+    /// Smallest cap known to succeed; 0 as long as not even the whole heap was tried
+    high: u32,
+    /// The cap of the probe that is running right now, 0 if none is
+    pending: u32,
+    results: [u32; MAX_TARGETS],
+}
+
+impl Search {
+    fn new(heap_size: usize) -> Self {
+        Self {
+            magic: MAGIC,
+            heap_size: heap_size as u32,
+            target: 0,
+            low: 0,
+            high: 0,
+            pending: 0,
+            results: [DOES_NOT_FIT; MAX_TARGETS],
+        }
+    }
+
+    fn target_count() -> usize {
+        ENGINES.len() * TARGETS_PER_ENGINE
+    }
+
+    /// The target and heap cap to probe next, `None` once the search is done
+    fn next_probe(&mut self) -> Option<(usize, u32)> {
+        loop {
+            if self.target as usize >= Self::target_count() {
+                return None;
+            }
+            if self.high == 0 {
+                return Some((self.target as usize, self.heap_size));
+            }
+            if self.high - self.low > GRANULARITY {
+                return Some((self.target as usize, self.low + (self.high - self.low) / 2));
+            }
+            self.finish_target(self.high);
+        }
+    }
+
+    fn record(&mut self, cap: u32, success: bool) {
+        match (success, self.high) {
+            (true, _) => self.high = cap,
+            // not even the whole heap is enough
+            (false, 0) => self.finish_target(DOES_NOT_FIT),
+            (false, _) => self.low = cap,
+        }
+    }
+
+    fn finish_target(&mut self, result: u32) {
+        self.results[self.target as usize] = result;
+        self.target += 1;
+        self.low = 0;
+        self.high = 0;
+    }
+
+    fn report(&self) {
+        log::info!(
+            "engine | target | smallest heap [B] with the {} allocator (-1: more than {})",
+            allocator::NAME,
+            self.heap_size
+        );
+        for (index, &result) in self.results[..Self::target_count()].iter().enumerate() {
+            let engine = &ENGINES[index / TARGETS_PER_ENGINE];
+            let target = match index % TARGETS_PER_ENGINE {
+                0 => "lifecycle",
+                workload => WORKLOADS[workload - 1].name,
+            };
+            let result = (result != DOES_NOT_FIT).then_some(result as usize);
+            log::info!("{} | {} | {}", engine.name, target, or_unknown(result));
+        }
+    }
+}
+
+fn run_probe(target: usize) -> bool {
+    let engine = &ENGINES[target / TARGETS_PER_ENGINE];
+    (engine.probe)(target % TARGETS_PER_ENGINE).is_ok()
+}
+
+/// On the host, the parent process drives the search and every probe is a child process
+#[cfg(feature = "board-host")]
+fn search(limit: &HeapLimit, heap_size: usize) {
+    use std::process::{Command, Stdio};
+
+    /// Tells a child process which target to probe with which cap (`<target> <cap>`)
+    const PROBE_ENV: &str = "OOM_SEARCH_PROBE";
+
+    if let Ok(probe) = std::env::var(PROBE_ENV) {
+        let mut numbers = probe.split(' ').map(|number| number.parse::<usize>());
+        let (Some(Ok(target)), Some(Ok(cap))) = (numbers.next(), numbers.next()) else {
+            std::process::exit(2);
+        };
+        limit.cap(cap);
+        // a failed allocation aborts the child, which the parent sees as a failure, too
+        std::process::exit(if run_probe(target) { 0 } else { 1 });
+    }
+
+    let executable = std::env::current_exe().expect("failed to find the own executable");
+    let mut search = Search::new(heap_size);
+    while let Some((target, cap)) = search.next_probe() {
+        let success = Command::new(&executable)
+            .env(PROBE_ENV, format!("{target} {cap}"))
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .is_ok_and(|status| status.success());
+        search.record(cap, success);
+    }
+    search.report();
+    std::process::exit(0);
+}
+
+/// On the boards, every boot runs one probe and resets
+#[cfg(not(feature = "board-host"))]
+fn search(limit: &HeapLimit, heap_size: usize) {
+    use core::mem::MaybeUninit;
+    use core::ptr::addr_of_mut;
+
+    #[link_section = ".uninit.OOM_SEARCH"]
+    static mut STATE: MaybeUninit<Search> = MaybeUninit::uninit();
+
+    // only we touch the state, and only from this task
+    let state = unsafe { &mut *addr_of_mut!(STATE) };
+    // the state is all `u32`s, so whatever the RAM held is a valid (if meaningless) value
+    let search = unsafe { state.assume_init_mut() };
+    if search.magic != MAGIC || search.heap_size != heap_size as u32 {
+        log::info!("starting the out-of-memory search");
+        *search = Search::new(heap_size);
+    }
+    if search.pending != 0 {
+        let cap = search.pending;
+        search.pending = 0;
+        log::info!("probe with {} bytes did not finish", cap);
+        search.record(cap, false);
+    }
+
+    let Some((target, cap)) = search.next_probe() else {
+        search.report();
+        // the next boot starts a new search
+        search.magic = 0;
+        #[cfg(feature = "board-qemu")]
+        crate::qemu::exit_success();
+        return;
+    };
+
+    search.pending = cap;
+    limit.cap(cap as usize);
+    let success = run_probe(target);
+    limit.remove();
+    search.pending = 0;
+    search.record(cap, success);
+    cortex_m::peripheral::SCB::sys_reset();
+}
+
+/// In the search a panic (usually a failed allocation) ends the probe, not the firmware
+#[cfg(all(feature = "oom-search", not(feature = "board-host")))]
+#[panic_handler]
+fn panic(info: &core::panic::PanicInfo) -> ! {
+    defmt::error!("probe panicked: {}", defmt::Display2Format(info));
+    cortex_m::peripheral::SCB::sys_reset()
+}
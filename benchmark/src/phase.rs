@@ -0,0 +1,337 @@
+//! The phases every engine goes through and the reporter that measures them
+//!
+//! [`crate::engine::run`] wraps the setup steps in [`PhaseReport::measure`], so all engines emit
+//! the same table (heap delta, heap peak, stack peak, time, cycles and the fragmentation of the
+//! heap afterwards per phase) no matter how different their APIs are.
+
+use embassy_time::Instant;
+
+use crate::error::BenchError;
+use crate::heap::{Fragmentation, HeapSnapshot, HeapStats};
+use crate::log;
+use crate::results::{self, or_unknown, ResultRecord};
+use crate::stack;
+use crate::timing::{self, Stopwatch};
+
+/// A measurement boundary in the lifecycle of a Wasm engine
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(not(feature = "board-host"), derive(defmt::Format))]
+pub enum Phase {
+    /// Creating the engine (and the store, for engines that separate the two)
+    EngineInit,
+    /// Validating the module on its own, for engines that do that before loading it
+    /// (see [`crate::engine::WasmEngine::VALIDATES`])
+    Validate,
+    /// Decoding or deserializing the module bytes
+    ModuleLoad,
+    /// Setting up the linker or the host imports
+    Link,
+    /// Instantiating the module
+    Instantiate,
+    /// The first call into the guest
+    FirstCall,
+    /// Calling into the guest again, once everything is warmed up
+    SteadyState,
+    /// Dropping the instance
+    DropInstance,
+    /// Dropping the module
+    DropModule,
+    /// Dropping the engine (and the store), which should give back everything that is left
+    DropEngine,
+}
+
+impl Phase {
+    pub const COUNT: usize = 10;
+
+    pub const ALL: [Phase; Phase::COUNT] = [
+        Phase::EngineInit,
+        Phase::Validate,
+        Phase::ModuleLoad,
+        Phase::Link,
+        Phase::Instantiate,
+        Phase::FirstCall,
+        Phase::SteadyState,
+        Phase::DropInstance,
+        Phase::DropModule,
+        Phase::DropEngine,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Phase::EngineInit => "engine init",
+            Phase::Validate => "validate",
+            Phase::ModuleLoad => "module load",
+            Phase::Link => "link",
+            Phase::Instantiate => "instantiate",
+            Phase::FirstCall => "first call",
+            Phase::SteadyState => "steady state",
+            Phase::DropInstance => "drop instance",
+            Phase::DropModule => "drop module",
+            Phase::DropEngine => "drop engine",
+        }
+    }
+
+    /// The name used in the machine-readable results
+    pub fn id(self) -> &'static str {
+        match self {
+            Phase::EngineInit => "engine_init",
+            Phase::Validate => "validate",
+            Phase::ModuleLoad => "module_load",
+            Phase::Link => "link",
+            Phase::Instantiate => "instantiate",
+            Phase::FirstCall => "first_call",
+            Phase::SteadyState => "steady_state",
+            Phase::DropInstance => "drop_instance",
+            Phase::DropModule => "drop_module",
+            Phase::DropEngine => "drop_engine",
+        }
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// What we measured for a single phase
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(not(feature = "board-host"), derive(defmt::Format))]
+pub struct PhaseRecord {
+    pub phase: Phase,
+    /// Change of the allocated bytes over the phase (negative if the phase freed memory)
+    pub heap_delta: isize,
+    /// Highest heap usage reached during the phase
+    pub heap_peak: usize,
+    /// State of the heap at the end of the phase
+    pub heap_after: HeapSnapshot,
+    /// Deepest main stack usage during the phase (`None` if the stack cannot be measured)
+    pub stack_peak: Option<usize>,
+    pub duration_us: u64,
+    /// Core clock cycles (nanoseconds on the host, `None` if the cycle counter does not run)
+    pub cycles: Option<u64>,
+    /// How fragmented the heap is at the end of the phase
+    pub fragmentation: Option<Fragmentation>,
+}
+
+/// Heap usage, stack usage and duration of one measured piece of code
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(not(feature = "board-host"), derive(defmt::Format))]
+pub struct Measurement {
+    /// Change of the allocated bytes (negative if the code freed memory)
+    pub heap_delta: isize,
+    /// Highest heap usage reached while the code ran
+    pub heap_peak: usize,
+    /// State of the heap afterwards
+    pub heap_after: HeapSnapshot,
+    /// Deepest main stack usage (`None` if the stack cannot be measured)
+    pub stack_peak: Option<usize>,
+    pub duration_us: u64,
+    /// Core clock cycles (nanoseconds on the host, `None` if the cycle counter does not run)
+    pub cycles: Option<u64>,
+    /// Allocations the allocator could not satisfy
+    pub failed_allocations: usize,
+    /// How fragmented the heap is afterwards
+    pub fragmentation: Option<Fragmentation>,
+}
+
+impl Measurement {
+    /// Blames `error` on the heap if an allocation failed while the code ran
+    pub fn classify(&self, error: BenchError) -> BenchError {
+        if self.failed_allocations > 0 {
+            BenchError::OutOfMemory
+        } else {
+            error
+        }
+    }
+}
+
+/// Runs `f` and measures its heap usage, stack usage, duration and cycles
+pub fn measure<R>(heap: &HeapStats, f: impl FnOnce() -> R) -> (R, Measurement) {
+    stack::paint_main_stack();
+    heap.reset_peak();
+    let before = heap.snapshot();
+    let start = Instant::now();
+    let stopwatch = Stopwatch::start();
+
+    let result = f();
+
+    let cycles = stopwatch.cycles();
+    let duration_us = start.elapsed().as_micros();
+    let stack_peak = stack::main_stack_high_water_mark();
+    let after = heap.snapshot();
+    let fragmentation = heap.fragmentation();
+    let measurement = Measurement {
+        heap_delta: after.current as isize - before.current as isize,
+        heap_peak: after.peak,
+        heap_after: after,
+        stack_peak,
+        duration_us,
+        cycles,
+        failed_allocations: after.failed_allocations - before.failed_allocations,
+        fragmentation,
+    };
+    (result, measurement)
+}
+
+/// Collects one [`PhaseRecord`] per phase for an engine
+///
+/// The records are kept in a fixed array, so the reporter itself does not allocate and does not
+/// show up in the numbers it reports. Every record is also emitted as a [`ResultRecord`].
+pub struct PhaseReport<'a> {
+    engine: &'static str,
+    module: &'static str,
+    heap: &'a HeapStats,
+    records: [Option<PhaseRecord>; Phase::COUNT],
+}
+
+impl<'a> PhaseReport<'a> {
+    pub fn new(engine: &'static str, module: &'static str, heap: &'a HeapStats) -> Self {
+        log::info!(
+            "{} | phase | heap delta [B] | heap peak [B] | heap after [B] | stack peak [B] | time [us] | {} | largest free [B] | free blocks | fragmentation [permille]",
+            engine,
+            timing::UNIT
+        );
+        Self {
+            engine,
+            module,
+            heap,
+            records: [None; Phase::COUNT],
+        }
+    }
+
+    /// Runs `f` as the given phase and records its heap usage, stack usage, duration and cycles
+    pub fn measure<R>(&mut self, phase: Phase, f: impl FnOnce() -> R) -> R {
+        self.measure_phase(phase, f).0
+    }
+
+    /// Like [`PhaseReport::measure`] for code that can fail; a failure is reported as
+    /// [`BenchError::OutOfMemory`] if an allocation failed during the phase
+    pub fn try_measure<T>(
+        &mut self,
+        phase: Phase,
+        f: impl FnOnce() -> Result<T, BenchError>,
+    ) -> Result<T, BenchError> {
+        let (result, measurement) = self.measure_phase(phase, f);
+        result.map_err(|error| measurement.classify(error))
+    }
+
+    fn measure_phase<R>(&mut self, phase: Phase, f: impl FnOnce() -> R) -> (R, Measurement) {
+        let (result, measurement) = measure(self.heap, f);
+        let record = PhaseRecord {
+            phase,
+            heap_delta: measurement.heap_delta,
+            heap_peak: measurement.heap_peak,
+            heap_after: measurement.heap_after,
+            stack_peak: measurement.stack_peak,
+            duration_us: measurement.duration_us,
+            cycles: measurement.cycles,
+            fragmentation: measurement.fragmentation,
+        };
+        self.log_record(&record);
+        self.emit_record(&record);
+        self.records[phase.index()] = Some(record);
+
+        (result, measurement)
+    }
+
+    pub fn engine(&self) -> &'static str {
+        self.engine
+    }
+
+    pub fn module(&self) -> &'static str {
+        self.module
+    }
+
+    /// The heap statistics the phases are measured with
+    pub fn heap(&self) -> &'a HeapStats {
+        self.heap
+    }
+
+    /// The record of a phase, if that phase was measured
+    pub fn record(&self, phase: Phase) -> Option<&PhaseRecord> {
+        self.records[phase.index()].as_ref()
+    }
+
+    /// Highest heap usage over all measured phases
+    pub fn heap_peak(&self) -> Option<usize> {
+        self.records
+            .iter()
+            .flatten()
+            .map(|record| record.heap_peak)
+            .max()
+    }
+
+    /// Time and cycles of the phases up to a callable instance (engine init to instantiate);
+    /// the cycles are `None` unless all of those phases have them
+    pub fn startup(&self) -> (u64, Option<u64>) {
+        const STARTUP: [Phase; 5] = [
+            Phase::EngineInit,
+            Phase::Validate,
+            Phase::ModuleLoad,
+            Phase::Link,
+            Phase::Instantiate,
+        ];
+        let records = || STARTUP.iter().filter_map(|&phase| self.record(phase));
+        let duration_us = records().map(|record| record.duration_us).sum();
+        let cycles = records().map(|record| record.cycles).sum();
+        (duration_us, cycles)
+    }
+
+    /// Deepest main stack usage over all measured phases
+    pub fn stack_peak(&self) -> Option<usize> {
+        self.records
+            .iter()
+            .flatten()
+            .filter_map(|record| record.stack_peak)
+            .max()
+    }
+
+    /// Prints the whole table again, e.g. once all phases are done
+    pub fn log(&self) {
+        for record in self.records.iter().flatten() {
+            self.log_record(record);
+        }
+        if let (Some(peak), Some(stack)) = (self.stack_peak(), stack::main_stack()) {
+            log::info!(
+                "{} | stack high-water mark: {} of {} bytes",
+                self.engine,
+                peak,
+                stack.size()
+            );
+        }
+    }
+
+    fn emit_record(&self, record: &PhaseRecord) {
+        ResultRecord {
+            engine: self.engine,
+            module: self.module,
+            phase: record.phase.id(),
+            heap_delta: record.heap_delta,
+            heap_peak: record.heap_peak,
+            heap_after: record.heap_after.current,
+            stack_peak: record.stack_peak,
+            flash_size: results::flash_size(),
+            duration_us: record.duration_us,
+            cycles: record.cycles,
+            fragmentation: record.fragmentation,
+        }
+        .emit();
+    }
+
+    fn log_record(&self, record: &PhaseRecord) {
+        let fragmentation = record.fragmentation;
+        log::info!(
+            "{} | {} | {} | {} | {} | {} | {} | {} | {} | {} | {}",
+            self.engine,
+            record.phase.name(),
+            record.heap_delta,
+            record.heap_peak,
+            record.heap_after.current,
+            or_unknown(record.stack_peak),
+            record.duration_us,
+            or_unknown(record.cycles),
+            or_unknown(fragmentation.map(|fragmentation| fragmentation.largest_free_block)),
+            or_unknown(fragmentation.map(|fragmentation| fragmentation.free_blocks)),
+            or_unknown(fragmentation.map(|fragmentation| fragmentation.permille()))
+        );
+    }
+}
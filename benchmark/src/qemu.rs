@@ -0,0 +1,83 @@
+//! Support for running the firmware in QEMU (`mps2-an505`) instead of on a board
+//!
+//! The Nordic boards get their time driver from `embassy-nrf`. QEMU has none of those peripherals,
+//! so we count SysTick wraps instead. The logs go out via semihosting (`defmt-semihosting`), and
+//! both the end of the benchmark and a crash end the emulation, so scripted runs do not hang.
+
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use core::task::Waker;
+
+use cortex_m::peripheral::{syst::SystClkSource, SYST};
+use cortex_m_rt::{exception, ExceptionFrame};
+use cortex_m_semihosting::debug::{self, EXIT_FAILURE, EXIT_SUCCESS};
+use embassy_time_driver::{Driver, TICK_HZ};
+
+/// The clock QEMU feeds the SysTick of the `mps2-an505` with
+const SYSCLK_HZ: u64 = 20_000_000;
+
+/// SysTick is a 24 bit counter, we let it run through its whole range
+const RELOAD: u32 = 0x00FF_FFFF;
+
+static STARTED: AtomicBool = AtomicBool::new(false);
+static WRAPS: AtomicU32 = AtomicU32::new(0);
+
+struct SysTickDriver;
+
+embassy_time_driver::time_driver_impl!(static DRIVER: SysTickDriver = SysTickDriver);
+
+impl Driver for SysTickDriver {
+    fn now(&self) -> u64 {
+        start_systick();
+
+        // re-read if the counter wrapped while we looked at it
+        // (a wrap whose interrupt is still pending, e.g. inside a critical section, is missed)
+        loop {
+            let wraps = WRAPS.load(Ordering::Relaxed);
+            let remaining = SYST::get_current();
+            if WRAPS.load(Ordering::Relaxed) == wraps {
+                let cycles = (u64::from(wraps) << 24) | u64::from(RELOAD - remaining);
+                return cycles * TICK_HZ / SYSCLK_HZ;
+            }
+        }
+    }
+
+    fn schedule_wake(&self, _at: u64, waker: &Waker) {
+        // we have no alarm, so the task is polled again right away and checks the time itself;
+        // the benchmark does not sleep, so busy waiting costs us nothing here
+        waker.wake_by_ref();
+    }
+}
+
+fn start_systick() {
+    if STARTED.swap(true, Ordering::Relaxed) {
+        return;
+    }
+
+    // we are the only user of SysTick
+    let mut syst = unsafe { cortex_m::Peripherals::steal() }.SYST;
+    syst.set_clock_source(SystClkSource::Core);
+    syst.set_reload(RELOAD);
+    syst.clear_current();
+    syst.enable_interrupt();
+    syst.enable_counter();
+}
+
+/// Ends the emulation once the benchmark is done
+pub(crate) fn exit_success() {
+    debug::exit(EXIT_SUCCESS);
+}
+
+#[exception]
+fn SysTick() {
+    WRAPS.fetch_add(1, Ordering::Relaxed);
+}
+
+#[exception]
+unsafe fn HardFault(_frame: &ExceptionFrame) -> ! {
+    // panic-probe ends a panic with an undefined instruction, which lands here; without a
+    // debugger attached we end the emulation instead of spinning forever
+    debug::exit(EXIT_FAILURE);
+    loop {
+        cortex_m::asm::wfi();
+    }
+}
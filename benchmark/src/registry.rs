@@ -0,0 +1,63 @@
+//! The guest modules compiled into the firmware
+//!
+//! Which modules there are is listed in a manifest (`modules.manifest` in the repository root, as
+//! written by `precompile`, or the file `BENCHMARK_MODULES` points to at build time): one line per
+//! module and format, see `build.rs`. The build script embeds every artifact an enabled engine can
+//! load and generates [`MODULES`] from it, so a firmware image runs several modules without being
+//! rebuilt.
+//!
+//! Every module has to export `run`, which returns the iteration count it is called with (see
+//! `crate::engine::call_run`), and may import nothing but `logging.log`. The checked workloads and
+//! the host-call measurements are exports of [`BENCHMARK_MODULE`] only; other modules go through
+//! the phases without them.
+
+/// The formats the engines load a module in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// The plain Wasm binary (wasmi)
+    Wasm,
+    /// Archived by tinywasm (`.tw`)
+    Tinywasm,
+    /// Precompiled for Pulley on the boards (`.cwasm`)
+    Cwasm,
+    /// Precompiled for Pulley on the 64 bit host (`.host.cwasm`)
+    HostCwasm,
+    /// Compiled by `wamrc` for Thumb (`.aot`)
+    Aot,
+    /// Compiled by `wamrc --xip` to run in place from flash (`.xip.aot`)
+    XipAot,
+}
+
+/// A guest module, in every format an engine of this firmware loads
+pub struct GuestModule {
+    /// Name of the module, as it appears in the results
+    pub name: &'static str,
+    artifacts: &'static [(Format, &'static [u8])],
+}
+
+impl GuestModule {
+    /// The module bytes in `format`, if the module was built in it
+    pub fn artifact(&self, format: Format) -> Option<&'static [u8]> {
+        self.artifacts
+            .iter()
+            .find(|(artifact, _)| *artifact == format)
+            .map(|&(_, bytes)| bytes)
+    }
+
+    /// Whether the module exports the workloads and host-call exports
+    pub fn is_benchmark_module(&self) -> bool {
+        self.name == BENCHMARK_MODULE
+    }
+}
+
+/// The module the workloads (see [`crate::workload`]) and host calls (see [`crate::hostcall`])
+/// belong to
+pub const BENCHMARK_MODULE: &str = "benchmark_module";
+
+// `pub const MODULES: &[GuestModule]`, in the order of the manifest
+include!(concat!(env!("OUT_DIR"), "/modules.rs"));
+
+/// The module called `name`, if it is compiled in
+pub fn find(name: &str) -> Option<&'static GuestModule> {
+    MODULES.iter().find(|module| module.name == name)
+}
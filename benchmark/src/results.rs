@@ -0,0 +1,109 @@
+//! Machine-readable result records
+//!
+//! Next to the human-readable tables, every measured phase is emitted as a single line of the form
+//!
+//! ```text
+//! @result v4 engine=wasmi board=nrf54 module=benchmark_module phase=engine_init heap_delta=... ...
+//! ```
+//!
+//! The keys and their order are fixed for a given version, so the host-side `results_decoder` can
+//! turn the log of any run into JSON or CSV. Values that could not be measured are emitted as `-1`.
+//! Besides the phases, `phase` can also be the name of a workload (see `crate::workload`).
+//! When adding or changing keys, bump the version and teach the decoder about it.
+//!
+//! Version 2 added `cycles`: core clock cycles of the phase on the boards, nanoseconds on the host
+//! (see `crate::timing`).
+//!
+//! Version 3 added `allocator` and `heap_size`: the allocator behind the heap and the size of the
+//! heap the firmware was built with (see `crate::allocator`).
+//!
+//! Version 4 added `largest_free`, `free_blocks` and `fragmentation` (in per mille): how the free
+//! heap is split up at the end of the phase (see `crate::heap::Fragmentation`).
+
+use crate::allocator;
+use crate::heap::Fragmentation;
+use crate::log;
+
+/// The board the firmware was built for
+pub const BOARD: &str = if cfg!(feature = "board-nrf54") {
+    "nrf54"
+} else if cfg!(feature = "board-nrf53") {
+    "nrf53"
+} else if cfg!(feature = "board-qemu") {
+    "qemu"
+} else if cfg!(feature = "board-host") {
+    "host"
+} else {
+    "unknown"
+};
+
+/// One row of the results: what we measured for one phase of one engine
+pub struct ResultRecord<'a> {
+    pub engine: &'a str,
+    pub module: &'a str,
+    pub phase: &'a str,
+    pub heap_delta: isize,
+    pub heap_peak: usize,
+    pub heap_after: usize,
+    pub stack_peak: Option<usize>,
+    pub flash_size: Option<usize>,
+    pub duration_us: u64,
+    pub cycles: Option<u64>,
+    pub fragmentation: Option<Fragmentation>,
+}
+
+impl ResultRecord<'_> {
+    /// Prints the record in the versioned line format
+    pub fn emit(&self) {
+        let fragmentation = self.fragmentation;
+        log::println!(
+            "@result v4 engine={} board={} module={} phase={} heap_delta={} heap_peak={} heap_after={} stack_peak={} flash_size={} time_us={} cycles={} allocator={} heap_size={} largest_free={} free_blocks={} fragmentation={}",
+            self.engine,
+            BOARD,
+            self.module,
+            self.phase,
+            self.heap_delta,
+            self.heap_peak,
+            self.heap_after,
+            or_unknown(self.stack_peak),
+            or_unknown(self.flash_size),
+            self.duration_us,
+            or_unknown(self.cycles),
+            allocator::NAME,
+            allocator::HEAP_SIZE,
+            or_unknown(fragmentation.map(|fragmentation| fragmentation.largest_free_block)),
+            or_unknown(fragmentation.map(|fragmentation| fragmentation.free_blocks)),
+            or_unknown(fragmentation.map(|fragmentation| fragmentation.permille()))
+        );
+    }
+}
+
+/// Values that could not be measured are shown as `-1`
+pub(crate) fn or_unknown(value: Option<impl TryInto<i64>>) -> i64 {
+    value
+        .and_then(|value| value.try_into().ok())
+        .unwrap_or(-1)
+}
+
+/// Size of the firmware image in flash: the code and read-only data plus the initial values of
+/// `.data`, as laid out by `cortex-m-rt`
+#[cfg(target_arch = "arm")]
+pub fn flash_size() -> Option<usize> {
+    extern "C" {
+        static __vector_table: u8;
+        static __sidata: u8;
+        static __sdata: u8;
+        static __edata: u8;
+    }
+
+    let start = core::ptr::addr_of!(__vector_table) as usize;
+    let data_load_address = core::ptr::addr_of!(__sidata) as usize;
+    let data_size = core::ptr::addr_of!(__edata) as usize - core::ptr::addr_of!(__sdata) as usize;
+    Some(data_load_address - start + data_size)
+}
+
+/// Off target the firmware is not in flash
+#[cfg(not(target_arch = "arm"))]
+pub fn flash_size() -> Option<usize> {
+    None
+}
@@ -0,0 +1,55 @@
+//! Where the module bytes come from
+//!
+//! The modules are linked into the firmware (`include_bytes!`), so on the boards they sit in the
+//! internal flash, which the CPU reads like RAM. Engines that work on the bytes where they are
+//! (wasmi parsing a slice, wasmtime with `Module::deserialize_raw`, WAMR with an XIP module) need no
+//! RAM to hold them.
+//!
+//! A module on an external flash or in a file system is not mapped like that. [`ModuleStorage`] is
+//! the interface to such a store, and [`ModuleReader`] hands a module out of it in chunks of at
+//! most [`CHUNK_SIZE`] bytes, so engines that can load from a stream show what that costs. The
+//! internal flash is one such storage as well ([`MappedModule`]), which lets us measure streaming
+//! without extra hardware.
+
+use crate::error::BenchError;
+
+/// Most bytes a single read from the storage returns, like one page of an external flash
+pub const CHUNK_SIZE: usize = 256;
+
+/// Storage a module can be read from piece by piece (an external flash, a file)
+pub trait ModuleStorage {
+    /// Copies the bytes from `offset` on into `buf`; returns how many it copied, 0 at the end
+    fn read_at(&mut self, offset: usize, buf: &mut [u8]) -> Result<usize, BenchError>;
+}
+
+/// A module in memory-mapped flash (or RAM), read through [`ModuleStorage`] anyway
+pub struct MappedModule(pub &'static [u8]);
+
+impl ModuleStorage for MappedModule {
+    fn read_at(&mut self, offset: usize, buf: &mut [u8]) -> Result<usize, BenchError> {
+        let rest = self.0.get(offset..).unwrap_or_default();
+        let count = buf.len().min(rest.len());
+        buf[..count].copy_from_slice(&rest[..count]);
+        Ok(count)
+    }
+}
+
+/// Reads a module front to back, in chunks of at most [`CHUNK_SIZE`] bytes
+pub struct ModuleReader<S> {
+    storage: S,
+    offset: usize,
+}
+
+impl<S: ModuleStorage> ModuleReader<S> {
+    pub fn new(storage: S) -> Self {
+        Self { storage, offset: 0 }
+    }
+
+    /// Reads the next bytes into `buf`; returns how many, 0 once the whole module was read
+    pub fn read(&mut self, buf: &mut [u8]) -> Result<usize, BenchError> {
+        let len = buf.len().min(CHUNK_SIZE);
+        let count = self.storage.read_at(self.offset, &mut buf[..len])?;
+        self.offset += count;
+        Ok(count)
+    }
+}
@@ -0,0 +1,255 @@
+//! Stack high-water-mark measurement via stack painting
+//!
+//! The unused part of the stack is filled with a known byte pattern. After the code we are
+//! interested in ran, we scan from the bottom of the stack upwards: everything that still holds
+//! the pattern was never touched, so the first overwritten byte marks the deepest point the stack
+//! reached.
+//!
+//! Embassy tasks are polled on the main stack, so that is where the guest execution shows up. The
+//! futures of the spawned tasks live in the task arena of `embassy-executor` instead, which hands
+//! out memory from its bottom up. It can not be painted with a pattern, since the executor keeps
+//! its own state in there, so it is cleared before the first task is spawned and zero is its
+//! paint: the highest byte that is not zero marks how much of the arena the tasks took.
+
+use core::ptr;
+
+/// The byte the unused stack is filled with
+pub const PAINT_BYTE: u8 = 0xC5;
+
+/// Space we leave untouched below the current stack pointer while painting (covers the frame of
+/// the painting function itself and anything it may call)
+const PAINT_MARGIN: usize = 256;
+
+/// A stack, given by its lowest address (`bottom`) and the address it starts growing down from
+/// (`top`); also used for the task arena, which grows up from `bottom`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(not(feature = "board-host"), derive(defmt::Format))]
+pub struct StackRegion {
+    pub bottom: usize,
+    pub top: usize,
+}
+
+impl StackRegion {
+    pub fn size(&self) -> usize {
+        self.top - self.bottom
+    }
+
+    /// Paints the stack from its bottom up to (excluding) `limit`
+    ///
+    /// # Safety
+    /// The memory between `bottom` and `limit` must not be in use.
+    pub unsafe fn paint_up_to(&self, limit: usize) {
+        let len = limit.min(self.top).saturating_sub(self.bottom);
+        unsafe { paint_raw(self.bottom as *mut u8, len) };
+    }
+
+    /// Number of bytes (counted from the top) that were used since the stack was painted
+    ///
+    /// # Safety
+    /// The whole region must be readable.
+    pub unsafe fn used_bytes(&self) -> usize {
+        self.size() - unsafe { count_painted_raw(self.bottom as *const u8, self.size()) }
+    }
+
+    /// Number of bytes (counted from the bottom) up to the highest one that is not zero
+    ///
+    /// # Safety
+    /// The whole region must be readable.
+    pub unsafe fn written_bytes(&self) -> usize {
+        self.size() - unsafe { count_zeroed_raw(self.bottom as *const u8, self.size()) }
+    }
+}
+
+/// Fills the buffer with the paint pattern
+pub fn paint(stack: &mut [u8]) {
+    unsafe { paint_raw(stack.as_mut_ptr(), stack.len()) }
+}
+
+/// Number of bytes at the top of the buffer that were overwritten since it was painted
+///
+/// The buffer is interpreted like a stack, i.e. it grows down from its end towards index 0.
+pub fn used_bytes(stack: &[u8]) -> usize {
+    stack.len() - unsafe { count_painted_raw(stack.as_ptr(), stack.len()) }
+}
+
+/// Number of bytes up to (and including) the highest one that is not zero
+///
+/// The buffer is interpreted like the task arena, i.e. it is filled from index 0 on.
+pub fn written_bytes(arena: &[u8]) -> usize {
+    arena.len() - unsafe { count_zeroed_raw(arena.as_ptr(), arena.len()) }
+}
+
+unsafe fn paint_raw(start: *mut u8, len: usize) {
+    for offset in 0..len {
+        // volatile, so the compiler does not get clever with memory it considers unused
+        unsafe { ptr::write_volatile(start.add(offset), PAINT_BYTE) };
+    }
+}
+
+unsafe fn count_painted_raw(start: *const u8, len: usize) -> usize {
+    let mut painted = 0;
+    while painted < len && unsafe { ptr::read_volatile(start.add(painted)) } == PAINT_BYTE {
+        painted += 1;
+    }
+    painted
+}
+
+/// Number of zero bytes at the end of the buffer
+unsafe fn count_zeroed_raw(start: *const u8, len: usize) -> usize {
+    let mut zeroed = 0;
+    while zeroed < len && unsafe { ptr::read_volatile(start.add(len - zeroed - 1)) } == 0 {
+        zeroed += 1;
+    }
+    zeroed
+}
+
+/// The main stack as laid out by `cortex-m-rt`: from the end of the static data up to
+/// `_stack_start`
+#[cfg(target_arch = "arm")]
+pub fn main_stack() -> Option<StackRegion> {
+    extern "C" {
+        static __sheap: u8;
+        static _stack_start: u8;
+    }
+
+    Some(StackRegion {
+        bottom: ptr::addr_of!(__sheap) as usize,
+        top: ptr::addr_of!(_stack_start) as usize,
+    })
+}
+
+/// Off target there is no stack layout we know about
+#[cfg(not(target_arch = "arm"))]
+pub fn main_stack() -> Option<StackRegion> {
+    None
+}
+
+/// Paints the part of the main stack below the caller's frame
+///
+/// Call this at boot to measure everything from then on, or again before a piece of code to
+/// measure only that code.
+#[inline(never)]
+pub fn paint_main_stack() {
+    let Some(stack) = main_stack() else {
+        return;
+    };
+
+    // the address of a local is close enough to the stack pointer for our purposes
+    let marker = 0u8;
+    let stack_pointer = core::hint::black_box(&marker) as *const u8 as usize;
+    unsafe { stack.paint_up_to(stack_pointer.saturating_sub(PAINT_MARGIN)) };
+}
+
+/// Deepest main stack usage (in bytes) since the last call of [`paint_main_stack`]
+pub fn main_stack_high_water_mark() -> Option<usize> {
+    main_stack().map(|stack| unsafe { stack.used_bytes() })
+}
+
+/// The task arena of `embassy-executor`, which `memory.x` puts into a section of its own
+///
+/// `None` if the linker found no arena to put there.
+#[cfg(target_arch = "arm")]
+pub fn task_arena() -> Option<StackRegion> {
+    extern "C" {
+        static __stask_arena: u8;
+        static __etask_arena: u8;
+    }
+
+    let arena = StackRegion {
+        bottom: ptr::addr_of!(__stask_arena) as usize,
+        top: ptr::addr_of!(__etask_arena) as usize,
+    };
+    (arena.size() > 0).then_some(arena)
+}
+
+/// Off target, the executor allocates the arena where we can not find it
+#[cfg(not(target_arch = "arm"))]
+pub fn task_arena() -> Option<StackRegion> {
+    None
+}
+
+/// Zeroes the task arena, which is what [`task_arena_high_water_mark`] measures against
+///
+/// The section of the arena is neither loaded nor zeroed by the runtime, so this has to run at
+/// boot, before the executor spawns the first task.
+///
+/// # Safety
+/// Nothing may be in the arena yet.
+pub unsafe fn clear_task_arena() {
+    if let Some(arena) = task_arena() {
+        unsafe { ptr::write_bytes(arena.bottom as *mut u8, 0, arena.size()) };
+    }
+}
+
+/// How many bytes of the task arena the tasks took since boot, and the size of the arena
+pub fn task_arena_high_water_mark() -> Option<(usize, usize)> {
+    task_arena().map(|arena| (unsafe { arena.written_bytes() }, arena.size()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn painted_stack_is_unused() {
+        let mut stack = [0u8; 128];
+        paint(&mut stack);
+        assert_eq!(used_bytes(&stack), 0);
+    }
+
+    #[test]
+    fn high_water_mark_is_the_deepest_write() {
+        let mut stack = [0u8; 128];
+        paint(&mut stack);
+        // the stack grows down from the end, a frame of 40 bytes and a deeper one of 20
+        stack[88..].fill(1);
+        stack[68..88].fill(2);
+        // a frame that left some bytes untouched still counts from its deepest write
+        stack[60] = 3;
+        assert_eq!(used_bytes(&stack), 68);
+
+        // painting again starts the measurement over
+        paint(&mut stack);
+        stack[120..].fill(0);
+        assert_eq!(used_bytes(&stack), 8);
+    }
+
+    #[test]
+    fn fully_used_stack() {
+        let mut stack = [0u8; 64];
+        paint(&mut stack);
+        stack[0] = 0;
+        assert_eq!(used_bytes(&stack), 64);
+    }
+
+    #[test]
+    fn region_matches_the_slice() {
+        let mut stack = [0u8; 96];
+        let region = StackRegion {
+            bottom: stack.as_ptr() as usize,
+            top: stack.as_ptr() as usize + stack.len(),
+        };
+        unsafe { region.paint_up_to(region.bottom + 80) };
+        assert_eq!(&stack[..80], &[PAINT_BYTE; 80]);
+        assert_eq!(&stack[80..], &[0; 16]);
+
+        stack[50..].fill(1);
+        assert_eq!(unsafe { region.used_bytes() }, 46);
+        assert_eq!(used_bytes(&stack), 46);
+    }
+
+    #[test]
+    fn arena_is_measured_from_the_bottom() {
+        let mut arena = [0u8; 256];
+        assert_eq!(written_bytes(&arena), 0);
+
+        // two futures, the second with zeros at its end
+        arena[..40].fill(7);
+        arena[48..90].fill(9);
+        arena[90..100].fill(0);
+        assert_eq!(written_bytes(&arena), 90);
+
+        arena[255] = 1;
+        assert_eq!(written_bytes(&arena), 256);
+    }
+}
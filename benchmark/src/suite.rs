@@ -0,0 +1,229 @@
+//! Runs every enabled engine with every module, one after another
+//!
+//! All `engine-*` features can be enabled in the same firmware image, but the engines never run at
+//! the same time: each one goes through all phases and drops everything it created before the next
+//! one starts. This happens once for every module of the registry (see `crate::registry`), module
+//! by module. Comparing the heap before and after an engine shows whether it returned all of its
+//! memory (and whether it left the free memory as much in one piece as it found it), and once all
+//! engines are done a table compares them.
+
+use crate::allocator;
+use crate::engine::{self, WasmEngine};
+use crate::error::BenchError;
+use crate::heap::{Fragmentation, HeapSnapshot, HeapStats};
+use crate::log;
+use crate::oom;
+use crate::phase::PhaseReport;
+use crate::registry::{GuestModule, MODULES};
+use crate::results::or_unknown;
+use crate::stack;
+use crate::timing;
+#[cfg(feature = "engine-tinywasm")]
+use crate::tiny::TinywasmEngine;
+#[cfg(feature = "engine-wamr")]
+use crate::wamr::WamrEngine;
+#[cfg(feature = "engine-wasmi")]
+use crate::wasmi::WasmiEngine;
+#[cfg(feature = "engine-wasmtime")]
+use crate::wasmtime::WasmtimeEngine;
+
+/// Goes through all phases with one engine and module, filling in the report
+type EngineRun = fn(&GuestModule, &mut PhaseReport) -> Result<(), BenchError>;
+
+/// An engine compiled into this firmware
+pub(crate) struct EngineEntry {
+    pub name: &'static str,
+    pub run: EngineRun,
+    /// Runs the engine once for the out-of-memory search
+    pub probe: oom::Probe,
+}
+
+impl EngineEntry {
+    // unused in a firmware without any `engine-*` feature
+    #[allow(dead_code)]
+    const fn new<E: WasmEngine>() -> Self {
+        Self {
+            name: E::NAME,
+            run: engine::run::<E>,
+            probe: oom::probe::<E>,
+        }
+    }
+}
+
+/// The engines compiled into this firmware, in the order they run
+pub(crate) const ENGINES: &[EngineEntry] = &[
+    #[cfg(feature = "engine-wasmi")]
+    EngineEntry::new::<WasmiEngine>(),
+    // the other configurations of wasmi, in the order of `wasmi::config::VARIANTS`
+    #[cfg(feature = "wasmi-matrix")]
+    EngineEntry::new::<WasmiEngine<1>>(),
+    #[cfg(feature = "wasmi-matrix")]
+    EngineEntry::new::<WasmiEngine<2>>(),
+    #[cfg(feature = "wasmi-matrix")]
+    EngineEntry::new::<WasmiEngine<3>>(),
+    #[cfg(feature = "wasmi-matrix")]
+    EngineEntry::new::<WasmiEngine<4>>(),
+    #[cfg(feature = "wasmi-matrix")]
+    EngineEntry::new::<WasmiEngine<5>>(),
+    #[cfg(feature = "engine-tinywasm")]
+    EngineEntry::new::<TinywasmEngine>(),
+    #[cfg(feature = "engine-wasmtime")]
+    EngineEntry::new::<WasmtimeEngine>(),
+    #[cfg(feature = "engine-wamr")]
+    EngineEntry::new::<WamrEngine>(),
+];
+
+/// How many engines this firmware runs
+pub(crate) const MAX_ENGINES: usize = ENGINES.len();
+
+/// How many times an engine goes through all phases: every engine with every module
+const MAX_RUNS: usize = MAX_ENGINES * MODULES.len();
+
+/// How an engine did over the whole run with one module
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(not(feature = "board-host"), derive(defmt::Format))]
+pub struct EngineSummary {
+    pub engine: &'static str,
+    pub module: &'static str,
+    /// Highest heap usage of the engine, on top of what was allocated before it started
+    pub heap_peak: Option<usize>,
+    /// Deepest main stack usage over all phases
+    pub stack_peak: Option<usize>,
+    /// Time from creating the engine until the module is instantiated
+    pub startup_us: u64,
+    /// Cycles from creating the engine until the module is instantiated (see `crate::timing`)
+    pub startup_cycles: Option<u64>,
+    /// Bytes still allocated after the engine dropped everything
+    pub leaked_bytes: isize,
+    /// Allocations that were not freed after the engine dropped everything
+    pub leaked_blocks: isize,
+    /// How fragmented the heap was after the engine dropped everything
+    pub fragmentation: Option<Fragmentation>,
+}
+
+impl EngineSummary {
+    pub fn leaked(&self) -> bool {
+        self.leaked_bytes != 0 || self.leaked_blocks != 0
+    }
+}
+
+#[embassy_executor::task]
+pub async fn benchmark_task(heap: &'static HeapStats) {
+    run_all(heap);
+
+    #[cfg(feature = "board-qemu")]
+    crate::qemu::exit_success();
+}
+
+/// Runs all enabled engines with all modules and prints the comparison table
+pub fn run_all(heap: &HeapStats) -> [Option<EngineSummary>; MAX_RUNS] {
+    log::info!(
+        "heap: {} bytes, {} allocator, {} modules",
+        allocator::HEAP_SIZE,
+        allocator::NAME,
+        MODULES.len()
+    );
+    let runs = MODULES
+        .iter()
+        .flat_map(|guest| ENGINES.iter().map(move |engine| (guest, engine)));
+    let mut summaries = [None; MAX_RUNS];
+    for (slot, (guest, engine)) in summaries.iter_mut().zip(runs) {
+        *slot = Some(run_engine(heap, guest, engine.name, engine.run));
+    }
+
+    log_summaries(&summaries);
+    summaries
+}
+
+/// Runs a single engine with `guest` and checks that it gave back all of its memory afterwards
+fn run_engine(
+    heap: &HeapStats,
+    guest: &'static GuestModule,
+    engine: &'static str,
+    run: EngineRun,
+) -> EngineSummary {
+    log::info!("{} | module {}", engine, guest.name);
+    heap.reset_peak();
+    let before = heap.snapshot();
+    let fragmentation_before = heap.fragmentation();
+
+    let mut report = PhaseReport::new(engine, guest.name, heap);
+    if let Err(err) = run(guest, &mut report) {
+        log::error!("{} failed with {}: {}", engine, guest.name, err);
+    }
+    report.log();
+
+    // the engine is gone by now, only the report (which does not allocate) is left
+    let after = heap.snapshot();
+    let (startup_us, startup_cycles) = report.startup();
+    let summary = EngineSummary {
+        engine,
+        module: guest.name,
+        heap_peak: report
+            .heap_peak()
+            .map(|peak| peak.saturating_sub(before.current)),
+        stack_peak: report.stack_peak(),
+        startup_us,
+        startup_cycles,
+        leaked_bytes: after.current as isize - before.current as isize,
+        leaked_blocks: outstanding_blocks(&after) - outstanding_blocks(&before),
+        fragmentation: heap.fragmentation(),
+    };
+
+    if summary.leaked() {
+        log::warn!(
+            "{} leaked {} bytes in {} blocks",
+            engine,
+            summary.leaked_bytes,
+            summary.leaked_blocks
+        );
+    } else {
+        log::info!("{} returned all of its memory", engine);
+    }
+    if let (Some(before), Some(after)) = (fragmentation_before, summary.fragmentation) {
+        if after.largest_free_block < before.largest_free_block {
+            log::warn!(
+                "{} left the heap fragmented: largest free block {} bytes (was {}), {} free blocks (were {})",
+                engine,
+                after.largest_free_block,
+                before.largest_free_block,
+                after.free_blocks,
+                before.free_blocks
+            );
+        }
+    }
+
+    summary
+}
+
+fn outstanding_blocks(snapshot: &HeapSnapshot) -> isize {
+    snapshot.allocations as isize - snapshot.frees as isize
+}
+
+fn log_summaries(summaries: &[Option<EngineSummary>]) {
+    log::info!(
+        "engine | module | heap peak [B] | stack peak [B] | startup [us] | startup [{}] | leaked [B] | leaked blocks | largest free [B] | free blocks | fragmentation [permille]",
+        timing::UNIT
+    );
+    for summary in summaries.iter().flatten() {
+        let fragmentation = summary.fragmentation;
+        log::info!(
+            "{} | {} | {} | {} | {} | {} | {} | {} | {} | {} | {}",
+            summary.engine,
+            summary.module,
+            or_unknown(summary.heap_peak),
+            or_unknown(summary.stack_peak),
+            summary.startup_us,
+            or_unknown(summary.startup_cycles),
+            summary.leaked_bytes,
+            summary.leaked_blocks,
+            or_unknown(fragmentation.map(|fragmentation| fragmentation.largest_free_block)),
+            or_unknown(fragmentation.map(|fragmentation| fragmentation.free_blocks)),
+            or_unknown(fragmentation.map(|fragmentation| fragmentation.permille()))
+        );
+    }
+    // the task futures are the same for every engine, so the arena is reported once
+    if let Some((used, size)) = stack::task_arena_high_water_mark() {
+        log::info!("task arena high-water mark: {} of {} bytes", used, size);
+    }
+}
@@ -0,0 +1,71 @@
+//! Cycle counting for the measured phases
+//!
+//! The embassy tick runs at 32.768 kHz, which is too coarse for the short phases (linking, dropping
+//! an instance, a single guest call). On the Cortex-M boards we read the DWT cycle counter
+//! (`CYCCNT`) instead, which counts every core clock cycle. The host has no such counter, so the
+//! host build falls back to `std::time::Instant` and counts nanoseconds instead of cycles.
+//!
+//! `CYCCNT` is 32 bits wide, so it wraps after about 33 s at 128 MHz; none of the phases comes close
+//! to that. QEMU does not emulate the counter, it stays at 0 there and the cycles are reported as
+//! unknown.
+
+/// Started right before the measured code, read right after it
+pub struct Stopwatch {
+    #[cfg(not(feature = "board-host"))]
+    start: u32,
+    #[cfg(feature = "board-host")]
+    start: std::time::Instant,
+}
+
+#[cfg(not(feature = "board-host"))]
+impl Stopwatch {
+    pub fn start() -> Self {
+        enable_cycle_counter();
+        Self {
+            start: cortex_m::peripheral::DWT::cycle_count(),
+        }
+    }
+
+    /// Core clock cycles since [`Stopwatch::start`] (`None` if the counter does not run)
+    pub fn cycles(&self) -> Option<u64> {
+        let cycles = cortex_m::peripheral::DWT::cycle_count().wrapping_sub(self.start);
+        // nothing we measure takes zero cycles, so the counter is not implemented
+        (cycles != 0).then_some(u64::from(cycles))
+    }
+}
+
+#[cfg(feature = "board-host")]
+impl Stopwatch {
+    pub fn start() -> Self {
+        Self {
+            start: std::time::Instant::now(),
+        }
+    }
+
+    /// Nanoseconds since [`Stopwatch::start`], standing in for the cycles of the boards
+    pub fn cycles(&self) -> Option<u64> {
+        u64::try_from(self.start.elapsed().as_nanos()).ok()
+    }
+}
+
+#[cfg(not(feature = "board-host"))]
+fn enable_cycle_counter() {
+    use core::sync::atomic::{AtomicBool, Ordering};
+
+    static ENABLED: AtomicBool = AtomicBool::new(false);
+    if ENABLED.swap(true, Ordering::Relaxed) {
+        return;
+    }
+
+    // the benchmark is the only user of the trace unit
+    let mut peripherals = unsafe { cortex_m::Peripherals::steal() };
+    peripherals.DCB.enable_trace();
+    peripherals.DWT.enable_cycle_counter();
+}
+
+/// What the cycles are counted in, for the table headers
+pub const UNIT: &str = if cfg!(feature = "board-host") {
+    "ns"
+} else {
+    "cycles"
+};
@@ -1,19 +1,41 @@
-use tinywasm::{Extern, Imports};
+extern crate alloc;
 
-pub(crate) fn setup_imports() -> Imports {
-    // note that we have to provide the args in opposite order
-    let log_fn = Extern::typed_func(|ctx, (len, ptr): (i32, i32)| {
-        let mem = ctx.exported_memory("memory").expect("failed to get memory");
-        let data = mem
-            .load(ptr as usize, len as usize)
-            .expect("failed to load memory slice");
-        let log_msg = str::from_utf8(data).expect("failed to read data as str");
+use alloc::rc::Rc;
+
+use tinywasm::{Error, Extern, Imports};
+
+use super::SharedHostState;
+use crate::error::{BenchError, Message};
+use crate::host::{HostError, LOG_IMPORT};
 
-        defmt::info!("module log: {}", log_msg);
+pub(crate) fn setup_imports(host: &SharedHostState) -> Result<Imports, BenchError> {
+    let host = Rc::clone(host);
+    // note that we have to provide the args in opposite order
+    let log_fn = Extern::typed_func(move |ctx, (len, ptr): (i32, i32)| {
+        let (ptr, len) = (ptr as u32, len as u32);
+        let mut host = host.borrow_mut();
+        // unlike the other engines, tinywasm has no store data to keep the memory in, and the
+        // function context only hands out memories by their export name
+        let Ok(mem) = ctx.exported_memory("memory") else {
+            return Err(trap(host.trap(LOG_IMPORT, HostError::NoMemory)));
+        };
+        // `load` checks the bounds (including an overflowing end) itself
+        let Ok(data) = mem.load(ptr as usize, len as usize) else {
+            return Err(trap(
+                host.trap(LOG_IMPORT, HostError::OutOfBounds { ptr, len }),
+            ));
+        };
+        host.log(data);
         Ok(())
     });
 
     let mut imports = Imports::new();
-    imports.define("logging", "log", log_fn).unwrap();
     imports
+        .define("logging", "log", log_fn)
+        .map_err(|err| BenchError::Link(Message::new(err)))?;
+    Ok(imports)
+}
+
+fn trap(message: &'static str) -> Error {
+    Error::Other(message.into())
 }
@@ -1,50 +1,134 @@
 extern crate alloc;
 
-use core::any::Any;
-
-use alloc::boxed::Box;
-use alloc::vec::Vec;
+use alloc::rc::Rc;
+use core::cell::RefCell;
 
 use tinywasm::{
     types::{TinyWasmModule, WasmValue},
-    Module, StackConfig, Store,
+    Imports, Module, ModuleInstance, StackConfig, Store,
 };
 
+use crate::engine::{Value, WasmEngine};
+use crate::error::{BenchError, Message};
+use crate::host::{HostState, HostStats, Limits, LogSink};
+use crate::registry::Format;
 use crate::tiny::imports::setup_imports;
 
 mod imports;
 
-#[allow(dead_code)]
-fn precompiled_module() -> Module {
-    let tw_wasm = include_bytes!("../../../benchmark_module.tw");
-    let tw_module =
-        TinyWasmModule::from_twasm(tw_wasm).expect("failed to read in tiny wasm module");
-    Module::from(tw_module)
+/// Size of a Wasm page in bytes
+const PAGE_SIZE: usize = 64 * 1024;
+
+/// The host state, shared with the closures of the imports (tinywasm has no store data); the
+/// imports look the memory up themselves, so there is no memory handle
+type SharedHostState = Rc<RefCell<HostState<()>>>;
+
+pub struct TinywasmEngine {
+    // tinywasm has no separate engine, the store is what holds the runtime state
+    store: Store,
+    imports: Option<Imports>,
+    host: SharedHostState,
 }
 
-#[embassy_executor::task]
-pub async fn wasm_task() {
-    let module = precompiled_module();
+impl WasmEngine for TinywasmEngine {
+    const NAME: &'static str = "tinywasm";
+    const FORMAT: Format = Format::Tinywasm;
+
+    // the module moves into the store when it is instantiated
+    type Module = Option<Module>;
+    type Instance = ModuleInstance;
+
+    fn new() -> Result<Self, BenchError> {
+        let stack_config = StackConfig::new()
+            .with_block_stack_init_size(0)
+            .with_value_stack_128_init_size(0)
+            .with_value_stack_64_init_size(0)
+            .with_value_stack_32_init_size(0)
+            .with_value_stack_ref_init_size(0);
+        Ok(Self {
+            store: Store::with_config(stack_config),
+            imports: None,
+            host: Rc::new(RefCell::new(HostState::new(Self::NAME, Limits::default()))),
+        })
+    }
+
+    fn load_module(&mut self, bytes: &'static [u8]) -> Result<Option<Module>, BenchError> {
+        let tw_module = TinyWasmModule::from_twasm(bytes)
+            .map_err(|_| BenchError::Decode("failed to read in tiny wasm module".into()))?;
+        Ok(Some(Module::from(tw_module)))
+    }
+
+    fn link_host_functions(&mut self) -> Result<(), BenchError> {
+        self.imports = Some(setup_imports(&self.host)?);
+        Ok(())
+    }
 
-    let stack_config = StackConfig::new()
-        .with_block_stack_init_size(0)
-        .with_value_stack_128_init_size(0)
-        .with_value_stack_64_init_size(0)
-        .with_value_stack_32_init_size(0)
-        .with_value_stack_ref_init_size(0);
-    let mut store = Store::with_config(stack_config);
+    fn instantiate(&mut self, module: &mut Option<Module>) -> Result<ModuleInstance, BenchError> {
+        module
+            .take()
+            .ok_or(BenchError::Instantiate(
+                "module is already instantiated".into(),
+            ))?
+            .instantiate(&mut self.store, self.imports.take())
+            .map_err(|err| BenchError::Instantiate(Message::new(err)))
+    }
 
-    let imports = setup_imports();
+    fn call(
+        &mut self,
+        instance: &mut ModuleInstance,
+        name: &str,
+        args: &[Value],
+    ) -> Result<Option<Value>, BenchError> {
+        let func = instance
+            .exported_func_untyped(&self.store, name)
+            .map_err(|_| BenchError::MissingExport(name.into()))?;
 
-    let instance = module
-        .instantiate(&mut store, Some(imports))
-        .expect("failed to instantiate");
+        let mut params = [const { WasmValue::I32(0) }; 4];
+        let params = params
+            .get_mut(..args.len())
+            .ok_or(BenchError::Unsupported("too many arguments"))?;
+        for (param, arg) in params.iter_mut().zip(args) {
+            *param = to_wasm_value(*arg);
+        }
 
-    // we retrieve functions the same way as before
-    let func = instance
-        .exported_func::<(), ()>(&store, "run")
-        .expect("failed to get function");
+        let results = func
+            .call(&mut self.store, params)
+            .map_err(|err| BenchError::Trap(Message::new(err)))?;
+        results.first().map(from_wasm_value).transpose()
+    }
+
+    fn memory_size(&mut self, instance: &ModuleInstance) -> Option<usize> {
+        let pages = instance
+            .exported_memory_mut(&mut self.store, "memory")
+            .ok()?
+            .page_count();
+        Some(pages * PAGE_SIZE)
+    }
+
+    fn set_log_sink(&mut self, sink: LogSink) {
+        self.host.borrow_mut().sink = sink;
+    }
+
+    fn host_stats(&mut self) -> HostStats {
+        self.host.borrow().stats
+    }
+}
+
+fn to_wasm_value(value: Value) -> WasmValue {
+    match value {
+        Value::I32(value) => WasmValue::I32(value),
+        Value::I64(value) => WasmValue::I64(value),
+        Value::F32(value) => WasmValue::F32(value),
+        Value::F64(value) => WasmValue::F64(value),
+    }
+}
 
-    func.call(&mut store, ())
-        .expect("failed to call function with tinywasm");
+fn from_wasm_value(value: &WasmValue) -> Result<Value, BenchError> {
+    match value {
+        WasmValue::I32(value) => Ok(Value::I32(*value)),
+        WasmValue::I64(value) => Ok(Value::I64(*value)),
+        WasmValue::F32(value) => Ok(Value::F32(*value)),
+        WasmValue::F64(value) => Ok(Value::F64(*value)),
+        _ => Err(BenchError::Unsupported("result type")),
+    }
 }
@@ -1,3 +1,14 @@
+extern crate alloc;
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::ptr::NonNull;
+
+use crate::engine::{Value, WasmEngine};
+use crate::error::{BenchError, Message};
+use crate::host::{HostError, HostState, HostStats, Limits, LogSink, LOG_IMPORT};
+use crate::log;
+use crate::registry::Format;
 use crate::wamr::{bindings::wasm_runtime_init, platform::register_stack_boundary};
 
 mod bindings {
@@ -6,122 +17,245 @@ mod bindings {
 
 mod platform;
 
-#[embassy_executor::task]
-pub async fn wasm_task() {
-    // set the stack boundary
-    let stack_start = 0u8;
-    let start_address = &stack_start as *const u8 as usize;
-    register_stack_boundary(start_address);
-
-    match fallible_logic() {
-        Ok(()) => (),
-        Err(e) => {
-            defmt::error!("WAMR engine initialization failed: {}", e);
-        }
-    }
-}
+/// Longest export name we can look up (WAMR wants it NUL-terminated)
+const MAX_NAME_LEN: usize = 63;
 
-fn fallible_logic() -> Result<(), &'static str> {
-    // Setting up the wamr engine
-    defmt::info!("Init of the WAMR engine");
+/// Stack of the execution environment the guest runs on, allocated from the WAMR heap
+const EXEC_ENV_STACK_SIZE: u32 = 8 * 1024;
 
-    init_wamr_runtime()?;
-    defmt::info!("WAMR engine initialized");
+pub struct WamrEngine {
+    // registered with WAMR, which keeps pointing to them until the runtime is destroyed
+    native_symbols: Vec<bindings::NativeSymbol>,
+    // the attachment of the native symbols; WAMR hands the host functions native pointers, so
+    // there is no memory handle. Owned by the engine, freed once the runtime is destroyed
+    host: NonNull<HostState<()>>,
+}
 
-    // registering the log function - native symbols have to live until the end (we have to write a proper safe wrapper here)
-    let native_symbols = [bindings::NativeSymbol {
-        // we want this to live as long as everything lives
-        symbol: c"log".as_ptr(),
-        func_ptr: log_host_function as *mut core::ffi::c_void,
-        signature: c"(*~)".as_ptr(), // this means pointer and length + no return -- this is a WAMR specific thing -- see its docs
-        attachment: core::ptr::null_mut(),
-    }];
+/// A loaded module together with the copy it was loaded from (WAMR keeps referencing it)
+pub struct WamrModule {
+    handle: bindings::wasm_module_t,
+    // `None` for an XIP module, which is used in place
+    _bytes: Option<Vec<u8>>,
+}
 
-    let success = unsafe {
-        bindings::wasm_runtime_register_natives(
-            c"logging".as_ptr(),
-            native_symbols.as_ptr() as *mut bindings::NativeSymbol,
-            native_symbols.len() as u32,
-        )
+/// An instance together with the execution environment all calls into it run in
+pub struct WamrInstance {
+    handle: bindings::wasm_module_inst_t,
+    exec_env: bindings::wasm_exec_env_t,
+}
+
+impl WasmEngine for WamrEngine {
+    const NAME: &'static str = "wamr";
+    const FORMAT: Format = if cfg!(feature = "wamr-xip") {
+        Format::XipAot
+    } else {
+        Format::Aot
     };
+    // `logging.log` is registered with the `(*~)` signature
+    const CHECKS_IMPORT_ARGUMENTS: bool = true;
+
+    type Module = WamrModule;
+    type Instance = WamrInstance;
+
+    fn new() -> Result<Self, BenchError> {
+        // set the stack boundary
+        let stack_start = 0u8;
+        let start_address = &stack_start as *const u8 as usize;
+        register_stack_boundary(start_address);
+
+        // Setting up the wamr engine
+        log::info!("Init of the WAMR engine");
+        init_wamr_runtime()?;
+        log::info!("WAMR engine initialized");
+
+        Ok(Self {
+            native_symbols: Vec::new(),
+            host: NonNull::from(Box::leak(Box::new(HostState::new(
+                Self::NAME,
+                Limits::default(),
+            )))),
+        })
+    }
 
-    if !success {
-        return Err("failed to register log function");
+    fn load_module(&mut self, bytes: &'static [u8]) -> Result<WamrModule, BenchError> {
+        check_aot_header(bytes)?;
+
+        // WAMR may write to the buffer of a module while loading it (relocations), so it gets a
+        // copy in RAM; an XIP module is only ever read, so it can stay in flash
+        let mut copy = (!cfg!(feature = "wamr-xip")).then(|| bytes.to_vec());
+        let buffer = match &mut copy {
+            Some(copy) => copy.as_mut_ptr(),
+            None => bytes.as_ptr().cast_mut(),
+        };
+        let handle = load_module(buffer, bytes.len())?;
+        log::info!("Module loaded");
+        Ok(WamrModule {
+            handle,
+            _bytes: copy,
+        })
     }
-    defmt::info!("Log function registered");
 
-    let wasm_bytes = include_bytes!("../../../benchmark_module.aot");
+    fn link_host_functions(&mut self) -> Result<(), BenchError> {
+        self.native_symbols.push(bindings::NativeSymbol {
+            symbol: c"log".as_ptr(),
+            func_ptr: log_host_function as *mut core::ffi::c_void,
+            signature: c"(*~)".as_ptr(), // this means pointer and length + no return -- this is a WAMR specific thing -- see its docs
+            attachment: self.host.as_ptr().cast(),
+        });
+
+        let success = unsafe {
+            bindings::wasm_runtime_register_natives(
+                c"logging".as_ptr(),
+                self.native_symbols.as_mut_ptr(),
+                self.native_symbols.len() as u32,
+            )
+        };
 
-    // ADD: Validate AOT file structure before loading
-    defmt::info!("AOT file size: {} bytes", wasm_bytes.len());
-    if wasm_bytes.len() < 16 {
-        return Err("AOT file too small");
+        if !success {
+            return Err(BenchError::Link("failed to register log function".into()));
+        }
+        log::info!("Log function registered");
+        Ok(())
     }
 
-    // Check magic number (first 4 bytes should be 0x746f6100 = "\0aot")
-    let magic = u32::from_le_bytes([wasm_bytes[0], wasm_bytes[1], wasm_bytes[2], wasm_bytes[3]]);
-    defmt::info!("AOT magic: 0x{:08x} (expected: 0x746f6100)", magic);
+    fn instantiate(&mut self, module: &mut WamrModule) -> Result<WamrInstance, BenchError> {
+        let handle = instantiate_module(module.handle)?;
+        // the environment (and its stack) is created once, so the calls do not allocate it anew
+        let exec_env =
+            unsafe { bindings::wasm_runtime_create_exec_env(handle, EXEC_ENV_STACK_SIZE) };
+        if exec_env.is_null() {
+            unsafe { bindings::wasm_runtime_deinstantiate(handle) };
+            // the environment and its stack are allocated from the WAMR heap
+            return Err(BenchError::OutOfMemory);
+        }
+        log::info!("Module instantiated");
+        Ok(WamrInstance { handle, exec_env })
+    }
 
-    if magic != 0x746f6100 {
-        return Err("Invalid AOT magic number");
+    fn call(
+        &mut self,
+        instance: &mut WamrInstance,
+        name: &str,
+        args: &[Value],
+    ) -> Result<Option<Value>, BenchError> {
+        call_function(instance, name, args)
+    }
+
+    fn memory_size(&mut self, instance: &WamrInstance) -> Option<usize> {
+        let memory = unsafe { bindings::wasm_runtime_get_default_memory(instance.handle) };
+        if memory.is_null() {
+            return None;
+        }
+        let pages = unsafe { bindings::wasm_memory_get_cur_page_count(memory) };
+        let page_size = unsafe { bindings::wasm_memory_get_bytes_per_page(memory) };
+        Some(pages as usize * page_size as usize)
     }
 
-    // Check version (bytes 4-7)
-    let version = u32::from_le_bytes([wasm_bytes[4], wasm_bytes[5], wasm_bytes[6], wasm_bytes[7]]);
-    defmt::info!("AOT version: {}", version);
+    fn set_log_sink(&mut self, sink: LogSink) {
+        // the guest is not running, so nothing else refers to the state right now
+        unsafe { self.host.as_mut() }.sink = sink;
+    }
+
+    fn host_stats(&mut self) -> HostStats {
+        unsafe { self.host.as_ref() }.stats
+    }
+}
+
+// WAMR does not free anything on its own: without these, everything shows up as leaked
+
+impl Drop for WamrEngine {
+    fn drop(&mut self) {
+        unsafe {
+            bindings::wasm_runtime_destroy();
+            drop(Box::from_raw(self.host.as_ptr()));
+        }
+    }
+}
 
-    let mut wasm_vec = wasm_bytes.to_vec();
-    let module = load_module(&mut wasm_vec)?;
-    defmt::info!("Module loaded");
+impl Drop for WamrModule {
+    fn drop(&mut self) {
+        unsafe { bindings::wasm_runtime_unload(self.handle) };
+    }
+}
 
-    let module_inst = instantiate_module(module)?;
-    defmt::info!("Module instantiated");
+impl Drop for WamrInstance {
+    fn drop(&mut self) {
+        unsafe {
+            bindings::wasm_runtime_destroy_exec_env(self.exec_env);
+            bindings::wasm_runtime_deinstantiate(self.handle);
+        }
+    }
+}
 
-    call_run_function(module_inst)?;
-    defmt::info!("run function of the module executed");
+/// Refuses anything that is obviously not an AOT module, before WAMR gets to parse it
+fn check_aot_header(wasm_bytes: &[u8]) -> Result<(), BenchError> {
+    if wasm_bytes.len() < 16 {
+        return Err(BenchError::Decode("AOT file too small".into()));
+    }
 
+    // Check magic number (first 4 bytes should be 0x746f6100 = "\0aot")
+    let magic = u32::from_le_bytes([wasm_bytes[0], wasm_bytes[1], wasm_bytes[2], wasm_bytes[3]]);
+    if magic != 0x746f6100 {
+        return Err(BenchError::Decode("Invalid AOT magic number".into()));
+    }
     Ok(())
 }
 
-fn init_wamr_runtime() -> Result<(), &'static str> {
+fn init_wamr_runtime() -> Result<(), BenchError> {
     let init_success = unsafe { wasm_runtime_init() };
     if !init_success {
-        return Err("Failed to initialize WAMR runtime");
+        return Err(BenchError::EngineInit(
+            "Failed to initialize WAMR runtime".into(),
+        ));
     }
     Ok(())
 }
 
 /// This is the function that will be called by the guest module to log a message
+///
+/// The `(*~)` signature makes WAMR check that pointer and length are inside the guest memory
+/// before it calls us, and trap otherwise; it only hands us a null pointer if there is no memory.
 /// # Safety
 /// This function is unsafe because it dereferences raw pointers and performs memory operations.
 /// The caller must ensure that the pointers are valid and that the memory is not corrupted.
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn log_host_function(
-    _exec_env: bindings::wasm_exec_env_t,
+    exec_env: bindings::wasm_exec_env_t,
     buffer: *const u8, // Changed from u32 - this is already the converted native pointer!
     length: u32,
 ) {
+    // the attachment we registered the symbol with in `link_host_functions`
+    let host =
+        unsafe { bindings::wasm_runtime_get_function_attachment(exec_env) }.cast::<HostState<()>>();
+    let Some(host) = (unsafe { host.as_mut() }) else {
+        log::error!("log function has no host state attached");
+        return;
+    };
+
     if buffer.is_null() {
-        defmt::info!("buffer pointer is null");
+        host.trap(LOG_IMPORT, HostError::NoMemory);
+        unsafe {
+            bindings::wasm_runtime_set_exception(
+                bindings::wasm_runtime_get_module_inst(exec_env),
+                c"module does not export its memory".as_ptr(),
+            )
+        };
         return;
     }
 
     let slice = unsafe { core::slice::from_raw_parts(buffer, length as usize) };
-    if let Ok(msg) = core::str::from_utf8(slice) {
-        defmt::info!("module log: {}", msg);
-    } else {
-        defmt::info!("module logged using an invalid string");
-    }
+    host.log(slice);
 }
 
-fn load_module(wasm_bytes: &mut [u8]) -> Result<bindings::wasm_module_t, &'static str> {
-    let error_buf = [0i8; 256]; // not really using it right now, but it's here for the API
+/// Loads the module in the `len` bytes at `buffer`, which have to stay there until it is unloaded
+fn load_module(buffer: *mut u8, len: usize) -> Result<bindings::wasm_module_t, BenchError> {
+    let mut error_buf = [0u8; 256];
 
     let module = unsafe {
         bindings::wasm_runtime_load(
-            wasm_bytes.as_mut_ptr(),
-            wasm_bytes.len() as u32,
-            error_buf.as_ptr() as *mut core::ffi::c_char,
+            buffer,
+            len as u32,
+            error_buf.as_mut_ptr().cast(),
             error_buf.len() as u32,
         )
     };
@@ -129,23 +263,24 @@ fn load_module(wasm_bytes: &mut [u8]) -> Result<bindings::wasm_module_t, &'stati
     if !module.is_null() {
         Ok(module)
     } else {
-        defmt::error!("looking at error message");
-        // ADD: Print the actual error message
-        let error_msg =
-            unsafe { core::ffi::CStr::from_ptr(error_buf.as_ptr() as *const core::ffi::c_char) };
-        if let Ok(msg) = error_msg.to_str() {
-            if !msg.is_empty() {
-                defmt::error!("WAMR load error: {}", msg);
-            }
-        }
-        Err("Failed to load module")
+        Err(BenchError::Decode(error_message(&error_buf)))
     }
 }
 
+/// What WAMR wrote into an error buffer (it is NUL-terminated, if WAMR wrote anything at all)
+fn error_message(error_buf: &[u8]) -> Message {
+    core::ffi::CStr::from_bytes_until_nul(error_buf)
+        .ok()
+        .and_then(|message| message.to_str().ok())
+        .filter(|message| !message.is_empty())
+        .unwrap_or("no error message")
+        .into()
+}
+
 fn instantiate_module(
     module: bindings::wasm_module_t,
-) -> Result<bindings::wasm_module_inst_t, &'static str> {
-    let error_buf = [0i8; 256]; // not really using it right now, but it's here for the API
+) -> Result<bindings::wasm_module_inst_t, BenchError> {
+    let mut error_buf = [0u8; 256];
 
     let default_stack_size = 8 * 1024; // like, for no reason
     let host_managed_heap_size = 16 * 1024; // like, for no reason times two
@@ -154,7 +289,7 @@ fn instantiate_module(
             module,
             default_stack_size,
             host_managed_heap_size,
-            error_buf.as_ptr() as *mut core::ffi::c_char,
+            error_buf.as_mut_ptr().cast(),
             error_buf.len() as u32,
         )
     };
@@ -164,73 +299,127 @@ fn instantiate_module(
         let exception = unsafe { bindings::wasm_runtime_get_exception(module_inst) };
         if !exception.is_null() {
             let exception_str = unsafe { core::ffi::CStr::from_ptr(exception) };
-            defmt::warn!(
+            log::warn!(
                 "Exception after instantiation (but instance created): {}",
                 exception_str.to_string_lossy().as_str()
             );
-        } else {
-            defmt::info!("no exceptions after instantiation");
         }
-
-        let error_msg = unsafe {
-            core::ffi::CStr::from_ptr(error_buf.as_ptr() as *const core::ffi::c_char)
-                .to_string_lossy()
-        };
-
-        if !error_msg.is_empty() && error_msg != "\0" {
-            defmt::info!("Error buffer after instantiation: {}", error_msg.as_str());
-        } else {
-            defmt::info!("also nothing in the error buffer");
+        if error_buf[0] != 0 {
+            log::warn!(
+                "Error buffer after instantiation: {}",
+                error_message(&error_buf)
+            );
         }
 
         Ok(module_inst)
     } else {
-        Err("Failed to instantiate module")
+        Err(BenchError::Instantiate(error_message(&error_buf)))
     }
 }
 
-fn call_run_function(module_inst: bindings::wasm_module_inst_t) -> Result<(), &'static str> {
-    // Look up the run function
+fn call_function(
+    instance: &WamrInstance,
+    name: &str,
+    args: &[Value],
+) -> Result<Option<Value>, BenchError> {
+    let module_inst = instance.handle;
+    // WAMR wants a NUL-terminated name, we build it on the stack so the call does not allocate
+    if name.len() > MAX_NAME_LEN {
+        return Err(BenchError::Unsupported("function name too long"));
+    }
+    let mut c_name = [0u8; MAX_NAME_LEN + 1];
+    c_name[..name.len()].copy_from_slice(name.as_bytes());
+
+    // Look up the function
     let function = unsafe {
         bindings::wasm_runtime_lookup_function(
             module_inst,
-            c"run".as_ptr() as *const core::ffi::c_char,
+            c_name.as_ptr() as *const core::ffi::c_char,
         )
     };
 
     if function.is_null() {
-        return Err("function 'run' not found");
+        return Err(BenchError::MissingExport(name.into()));
     }
 
-    // create the execution env for the function
-    let stack_size = 8 * 1024;
-    let exec_env = unsafe { bindings::wasm_runtime_create_exec_env(module_inst, stack_size) };
-
-    if exec_env.is_null() {
-        return Err("failed to create exec environment");
+    let mut params = [wasm_val(Value::I32(0)); 4];
+    let params = params
+        .get_mut(..args.len())
+        .ok_or(BenchError::Unsupported("too many arguments"))?;
+    for (param, arg) in params.iter_mut().zip(args) {
+        *param = wasm_val(*arg);
     }
 
-    let mut argv = [0u32; 2]; // we need to allocate space for the way Wamr uses the arg vector internally, even if we don't need args
-
-    defmt::info!("about to call run function");
+    let mut results = [wasm_val(Value::I32(0)); 1];
+    let result_count = unsafe { bindings::wasm_func_get_result_count(function, module_inst) };
+    let results = results
+        .get_mut(..result_count as usize)
+        .ok_or(BenchError::Unsupported("too many results"))?;
 
-    let success =
-        unsafe { bindings::wasm_runtime_call_wasm(exec_env, function, 0, argv.as_mut_ptr()) }; // no arguments
-
-    // cleanup
-    unsafe {
-        bindings::wasm_runtime_destroy_exec_env(exec_env);
-    }
+    let success = unsafe {
+        bindings::wasm_runtime_call_wasm_a(
+            instance.exec_env,
+            function,
+            results.len() as u32,
+            results.as_mut_ptr(),
+            params.len() as u32,
+            params.as_mut_ptr(),
+        )
+    };
 
     if !success {
         let exception = unsafe { bindings::wasm_runtime_get_exception(module_inst) };
 
         if !exception.is_null() {
-            // let exception_str = unsafe { core::ffi::CStr::from_ptr(exception) };
-            return Err("Wasm exception");
+            let exception = unsafe { core::ffi::CStr::from_ptr(exception) };
+            let message = Message::new(exception.to_str().unwrap_or("<not UTF-8>"));
+            // the instance stays usable after a trap, as with the other engines
+            unsafe { bindings::wasm_runtime_clear_exception(module_inst) };
+            return Err(BenchError::Trap(message));
         } else {
-            return Err("failed to call 'run' function");
+            return Err(BenchError::Trap("failed to call function".into()));
+        }
+    }
+
+    results.first().map(value).transpose()
+}
+
+fn wasm_val(value: Value) -> bindings::wasm_val_t {
+    let mut val: bindings::wasm_val_t = unsafe { core::mem::zeroed() };
+    match value {
+        Value::I32(value) => {
+            val.kind = bindings::wasm_valkind_enum_WASM_I32 as bindings::wasm_valkind_t;
+            val.of.i32 = value;
+        }
+        Value::I64(value) => {
+            val.kind = bindings::wasm_valkind_enum_WASM_I64 as bindings::wasm_valkind_t;
+            val.of.i64 = value;
+        }
+        Value::F32(value) => {
+            val.kind = bindings::wasm_valkind_enum_WASM_F32 as bindings::wasm_valkind_t;
+            val.of.f32 = value;
+        }
+        Value::F64(value) => {
+            val.kind = bindings::wasm_valkind_enum_WASM_F64 as bindings::wasm_valkind_t;
+            val.of.f64 = value;
+        }
+    }
+    val
+}
+
+fn value(val: &bindings::wasm_val_t) -> Result<Value, BenchError> {
+    let kind = val.kind as u32;
+    unsafe {
+        if kind == bindings::wasm_valkind_enum_WASM_I32 {
+            Ok(Value::I32(val.of.i32))
+        } else if kind == bindings::wasm_valkind_enum_WASM_I64 {
+            Ok(Value::I64(val.of.i64))
+        } else if kind == bindings::wasm_valkind_enum_WASM_F32 {
+            Ok(Value::F32(val.of.f32))
+        } else if kind == bindings::wasm_valkind_enum_WASM_F64 {
+            Ok(Value::F64(val.of.f64))
+        } else {
+            Err(BenchError::Unsupported("result type"))
         }
     }
-    Ok(())
 }
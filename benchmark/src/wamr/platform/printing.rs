@@ -1,7 +1,9 @@
 use core::ffi::{c_char, c_int, CStr, VaList};
 use core::fmt::Write;
 
-/// Simple printf implementation using defmt
+use crate::log;
+
+/// Simple printf implementation that prints through `crate::log`
 /// This handles basic format specifiers that WAMR uses
 #[no_mangle]
 pub unsafe extern "C" fn os_printf(format: *const c_char, mut args: ...) -> c_int {
@@ -70,14 +72,14 @@ pub unsafe extern "C" fn os_printf(format: *const c_char, mut args: ...) -> c_in
             }
         }
 
-        defmt::info!("{}", result.as_str());
+        log::info!("{}", result.as_str());
         result.len() as c_int
     } else {
         0
     }
 }
 
-/// vprintf implementation using defmt
+/// vprintf implementation that prints through `crate::log`
 #[allow(unused_mut)] // ap needs to be mutable to pass to format_va_list
 #[no_mangle]
 pub extern "C" fn os_vprintf(format: *const c_char, mut ap: VaList) -> c_int {
@@ -88,7 +90,7 @@ pub extern "C" fn os_vprintf(format: *const c_char, mut ap: VaList) -> c_int {
     unsafe {
         if let Ok(format_str) = CStr::from_ptr(format).to_str() {
             let formatted = format_va_list(format_str, ap);
-            defmt::info!("{}", formatted.as_str());
+            log::info!("{}", formatted.as_str());
             formatted.len() as c_int
         } else {
             0
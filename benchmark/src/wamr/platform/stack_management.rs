@@ -9,13 +9,16 @@ static STACK_BOUNDARY: AtomicUsize = AtomicUsize::new(0);
  * than a fix margin from an area near the stack start. And the resulting maximal address is what we
  * provide WAMR as the address to check for stack overflow.
  *
- * Actually, in newer embassy versions, we could manage our task stack directly and provide the actual bound.
- * that is what we should do if we were to seriously integrate WAMR into our stack.
+ * The guess is clamped to the actual bottom of the main stack (the same region the stack painting
+ * in `crate::stack` measures), so WAMR never assumes it may grow into the static data.
  */
 const STACK_SAFETY_MARGIN: usize = 8 * 1024;
 
 pub fn register_stack_boundary(stack_start: usize) {
-    let boundary = stack_start.saturating_sub(STACK_SAFETY_MARGIN);
+    let mut boundary = stack_start.saturating_sub(STACK_SAFETY_MARGIN);
+    if let Some(stack) = crate::stack::main_stack() {
+        boundary = boundary.max(stack.bottom);
+    }
     STACK_BOUNDARY.store(boundary, Ordering::Relaxed);
 }
 
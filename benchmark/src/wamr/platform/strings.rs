@@ -1,5 +1,7 @@
 use core::ffi::{c_char, c_int};
 
+use crate::log;
+
 /// Simple strcmp implementation for embedded use
 /// Compares two null-terminated strings
 /// Returns: 0 if equal, <0 if s1 < s2, >0 if s1 > s2
@@ -115,9 +117,9 @@ pub extern "C" fn memcmp(
 /// Called when an assertion fails in WAMR
 #[no_mangle]
 pub extern "C" fn abort() -> ! {
-    // Log the abort (if defmt is available)
+    // Log the abort (if logging still works)
     // Note: This might not work if we're in a bad state, but it's worth trying
-    defmt::error!("abort() called - assertion failed or fatal error");
+    log::error!("abort() called - assertion failed or fatal error");
 
     // Loop forever - in embedded systems, abort typically doesn't return
     loop {
@@ -0,0 +1,105 @@
+//! The configurations of wasmi the benchmark compares
+//!
+//! wasmi can translate a module to its internal bytecode up front (eager), function by function
+//! on the first call (lazy translation), or validate lazily, too (lazy). The lazy modes trade
+//! startup time and heap for work during the first calls, which is exactly what we want to see on
+//! an MCU. Every [`Variant`] runs as an engine of its own (see `crate::suite`): without the
+//! `wasmi-matrix` feature only the first one, with it all of them, so their phases, heap peaks and
+//! startup times end up side by side in the results.
+//!
+//! Modules are validated before wasmi loads them, in a phase of their own (`validate`), so the
+//! cost of validation shows up apart from loading. Loading without validating is unsafe for
+//! modules we do not trust, so it takes the `wasmi-unchecked` feature: it leaves out the
+//! validation of every variant.
+//!
+//! wasmi parses modules from a slice, which it reads in place. `wasmi-streaming` loads the module
+//! through a [`ModuleReader`](crate::source::ModuleReader) instead, a chunk at a time, the way a
+//! module on an external flash or in a file would be loaded; wasmi validates while it parses a
+//! stream, so that variant has no validation phase.
+
+use wasmi::CompilationMode;
+
+/// One configuration of wasmi, run as an engine named `name`
+pub struct Variant {
+    pub name: &'static str,
+    pub compilation: CompilationMode,
+    /// Fuel the guest starts with; `None` turns fuel metering off
+    pub fuel: Option<u64>,
+    /// `None` keeps the defaults of wasmi
+    pub stack: Option<StackLimits>,
+    /// Validates the whole module before loading it (`Module::validate`); otherwise wasmi
+    /// validates each function when it compiles it, which for the lazy mode is on its first call
+    pub validate: bool,
+    /// Loads the module from a stream instead of the slice (see `crate::source`)
+    pub streaming: bool,
+}
+
+/// Limits of the stacks wasmi executes on (see `wasmi::Config`)
+pub struct StackLimits {
+    pub min_height: usize,
+    pub max_height: usize,
+    pub max_recursion_depth: usize,
+    /// Stacks kept around for the next call instead of being freed
+    pub max_cached_stacks: usize,
+}
+
+/// All variants; the first one is the plain `wasmi` engine
+pub const VARIANTS: &[Variant] = &[
+    Variant {
+        name: "wasmi",
+        compilation: CompilationMode::Eager,
+        fuel: None,
+        stack: None,
+        validate: true,
+        streaming: false,
+    },
+    Variant {
+        name: "wasmi-lazy-translation",
+        compilation: CompilationMode::LazyTranslation,
+        fuel: None,
+        stack: None,
+        validate: true,
+        streaming: false,
+    },
+    Variant {
+        name: "wasmi-lazy",
+        compilation: CompilationMode::Lazy,
+        fuel: None,
+        stack: None,
+        // validating up front would undo the lazy validation we want to measure
+        validate: false,
+        streaming: false,
+    },
+    Variant {
+        name: "wasmi-fuel",
+        compilation: CompilationMode::Eager,
+        // metering is what we measure, not running out
+        fuel: Some(u64::MAX),
+        stack: None,
+        validate: true,
+        streaming: false,
+    },
+    // deep enough for the `recursion` workload
+    Variant {
+        name: "wasmi-small-stack",
+        compilation: CompilationMode::Eager,
+        fuel: None,
+        stack: Some(StackLimits {
+            min_height: 256,
+            max_height: 16 * 1024,
+            max_recursion_depth: 64,
+            max_cached_stacks: 0,
+        }),
+        validate: true,
+        streaming: false,
+    },
+    Variant {
+        name: "wasmi-streaming",
+        compilation: CompilationMode::Eager,
+        fuel: None,
+        stack: None,
+        // the stream is validated while it is parsed, there is nothing to validate up front
+        validate: false,
+        streaming: true,
+    },
+];
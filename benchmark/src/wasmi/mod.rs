@@ -1,32 +1,160 @@
-use crate::wasmi::wasm::{init_runtime, instantiate_module, Runtime};
+use wasmi::{Engine, Instance, Linker, Module, Store, Val};
+
+use crate::engine::{Value, WasmEngine};
+use crate::error::{BenchError, Message};
+use crate::host::{HostStats, Limits, LogSink};
+use crate::registry::Format;
+use crate::source::{MappedModule, ModuleReader};
+use crate::wasmi::config::{Variant, VARIANTS};
+use crate::wasmi::wasm::{create_engine, create_linker, ModuleStream, StoreData};
 
 extern crate alloc;
 
+pub mod config;
 mod wasm;
 
-#[embassy_executor::task]
-pub async fn wasm_task() {
-    let runtime = init_runtime().expect("failed to init runtime");
-    let Runtime {
-        store,
-        module,
-        mut linker,
-        ..
-    } = runtime;
-
-    let (mut store, running) = match instantiate_module(store, module, &mut linker) {
-        Ok(res) => res,
-        Err(err) => {
-            defmt::error!("wasm error: {}", err);
-            return;
+/// wasmi, configured as the variant `VARIANT` of [`VARIANTS`] (by default the plain one)
+pub struct WasmiEngine<const VARIANT: usize = 0> {
+    engine: Engine,
+    store: Store<StoreData>,
+    linker: Option<Linker<StoreData>>,
+}
+
+impl<const VARIANT: usize> WasmiEngine<VARIANT> {
+    const CONFIG: &'static Variant = &VARIANTS[VARIANT];
+}
+
+impl<const VARIANT: usize> WasmEngine for WasmiEngine<VARIANT> {
+    const NAME: &'static str = Self::CONFIG.name;
+    const VALIDATES: bool = Self::CONFIG.validate && !cfg!(feature = "wasmi-unchecked");
+    const FORMAT: Format = Format::Wasm;
+
+    type Module = Module;
+    type Instance = Instance;
+
+    fn new() -> Result<Self, BenchError> {
+        let limits = Limits {
+            fuel: Self::CONFIG.fuel,
+            ..Limits::default()
+        };
+        let state = StoreData::new(Self::NAME, limits);
+        let engine = create_engine(Self::CONFIG, &state.limits);
+        let fuel = state.limits.fuel;
+        let mut store = Store::new(&engine, state);
+        if let Some(fuel) = fuel {
+            store
+                .set_fuel(fuel)
+                .map_err(|err| BenchError::EngineInit(Message::new(err)))?;
+        }
+        Ok(Self {
+            engine,
+            store,
+            linker: None,
+        })
+    }
+
+    fn validate_module(&mut self, bytes: &[u8]) -> Result<(), BenchError> {
+        Module::validate(&self.engine, bytes).map_err(|err| BenchError::Invalid(Message::new(err)))
+    }
+
+    fn load_module(&mut self, bytes: &'static [u8]) -> Result<Module, BenchError> {
+        let module = if Self::CONFIG.streaming {
+            let stream = ModuleStream(ModuleReader::new(MappedModule(bytes)));
+            if cfg!(feature = "wasmi-unchecked") {
+                unsafe { Module::new_streaming_unchecked(&self.engine, stream) }
+            } else {
+                Module::new_streaming(&self.engine, stream)
+            }
+        } else if Self::VALIDATES || cfg!(feature = "wasmi-unchecked") {
+            // with `VALIDATES` the module was validated in a phase of its own already
+            unsafe { Module::new_unchecked(&self.engine, bytes) }
+        } else {
+            Module::new(&self.engine, bytes)
+        };
+        module.map_err(|err| BenchError::Decode(Message::new(err)))
+    }
+
+    fn link_host_functions(&mut self) -> Result<(), BenchError> {
+        self.linker = Some(create_linker(&self.engine)?);
+        Ok(())
+    }
+
+    fn instantiate(&mut self, module: &mut Module) -> Result<Instance, BenchError> {
+        let linker = self
+            .linker
+            .as_ref()
+            .ok_or(BenchError::Link("host functions are not linked".into()))?;
+        let instance = linker
+            .instantiate_and_start(&mut self.store, module)
+            .map_err(|err| BenchError::Instantiate(Message::new(err)))?;
+
+        // resolved once here, so `logging.log` does not look the export up on every call
+        let memory = instance.get_memory(&self.store, "memory");
+        self.store.data_mut().memory = memory;
+        Ok(instance)
+    }
+
+    fn call(
+        &mut self,
+        instance: &mut Instance,
+        name: &str,
+        args: &[Value],
+    ) -> Result<Option<Value>, BenchError> {
+        let func = instance
+            .get_func(&self.store, name)
+            .ok_or_else(|| BenchError::MissingExport(name.into()))?;
+
+        let mut params = [const { Val::I32(0) }; 4];
+        let params = params
+            .get_mut(..args.len())
+            .ok_or(BenchError::Unsupported("too many arguments"))?;
+        for (param, arg) in params.iter_mut().zip(args) {
+            *param = to_val(*arg);
         }
-    };
 
-    let led_fn = running
-        .get_typed_func::<(), ()>(&mut store, "run")
-        .expect("failed to get function");
+        let mut results = [const { Val::I32(0) }; 1];
+        let results = match func.ty(&self.store).results().first() {
+            Some(ty) => {
+                results[0] = Val::default(*ty);
+                &mut results[..]
+            }
+            None => &mut results[..0],
+        };
+
+        func.call(&mut self.store, params, results)
+            .map_err(|err| BenchError::Trap(Message::new(err)))?;
+        results.first().map(from_val).transpose()
+    }
+
+    fn memory_size(&mut self, _instance: &Instance) -> Option<usize> {
+        let memory = self.store.data().memory?;
+        Some(memory.data(&self.store).len())
+    }
+
+    fn set_log_sink(&mut self, sink: LogSink) {
+        self.store.data_mut().sink = sink;
+    }
+
+    fn host_stats(&mut self) -> HostStats {
+        self.store.data().stats
+    }
+}
+
+fn to_val(value: Value) -> Val {
+    match value {
+        Value::I32(value) => Val::I32(value),
+        Value::I64(value) => Val::I64(value),
+        Value::F32(value) => Val::F32(value.into()),
+        Value::F64(value) => Val::F64(value.into()),
+    }
+}
 
-    led_fn
-        .call(store, ())
-        .expect("failed to call run function with wasmi");
+fn from_val(val: &Val) -> Result<Value, BenchError> {
+    match val {
+        Val::I32(value) => Ok(Value::I32(*value)),
+        Val::I64(value) => Ok(Value::I64(*value)),
+        Val::F32(value) => Ok(Value::F32((*value).into())),
+        Val::F64(value) => Ok(Value::F64((*value).into())),
+        _ => Err(BenchError::Unsupported("result type")),
+    }
 }
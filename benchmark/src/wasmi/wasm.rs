@@ -1,77 +1,65 @@
-use core::str;
+use wasmi::errors::ReadError;
+use wasmi::{Caller, Config, Engine, Error, Linker, Memory, Read};
 
-use wasmi::{AsContext, Caller, Config, Engine, Instance, Linker, Module, Store};
+use crate::error::{BenchError, Message};
+use crate::host::{HostState, Limits};
+use crate::source::{ModuleReader, ModuleStorage};
+use crate::wasmi::config::Variant;
 
 extern crate alloc;
 
-pub(super) struct Runtime {
-    pub(super) _engine: Engine,
-    pub(super) store: Store<()>,
-    pub(super) module: Module,
-    pub(super) linker: Linker<()>,
-}
+/// The data of our store
+pub type StoreData = HostState<Memory>;
 
-pub fn init_runtime() -> Result<Runtime, &'static str> {
+pub fn create_engine(variant: &Variant, limits: &Limits) -> Engine {
     let mut cfg = Config::default();
-    cfg.compilation_mode(wasmi::CompilationMode::Eager);
-    let engine = Engine::new(&cfg);
-
-    static WASM: &[u8] = include_bytes!(
-        "../../../benchmark_module/target/wasm32-unknown-unknown/release/benchmark_module.wasm"
-    ); // the module we loaded
-    let module =
-        unsafe { Module::new_unchecked(&engine, WASM).map_err(|_e| "failed to load module")? };
+    cfg.compilation_mode(variant.compilation);
+    cfg.consume_fuel(limits.fuel.is_some());
+    if let Some(stack) = &variant.stack {
+        cfg.set_min_stack_height(stack.min_height)
+            .set_max_stack_height(stack.max_height)
+            .set_max_recursion_depth(stack.max_recursion_depth)
+            .set_max_cached_stacks(stack.max_cached_stacks);
+    }
+    Engine::new(&cfg)
+}
 
-    let state = ();
-    let store = Store::new(&engine, state);
-    let mut linker = <Linker<()>>::new(&engine);
-    link_host_functions(&mut linker)?;
+/// Feeds the streaming parser of wasmi from a [`ModuleReader`]
+pub struct ModuleStream<S>(pub ModuleReader<S>);
 
-    Ok(Runtime {
-        _engine: engine,
-        store,
-        module,
-        linker,
-    })
+impl<S: ModuleStorage> Read for ModuleStream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, ReadError> {
+        self.0.read(buf).map_err(|_| ReadError::UnknownError)
+    }
 }
 
-pub fn instantiate_module(
-    mut store: Store<()>,
-    module: Module,
-    linker: &mut Linker<()>,
-) -> Result<(Store<()>, Instance), &'static str> {
-    let instance = linker
-        .instantiate_and_start(&mut store, &module)
-        .expect("failed to start instance");
-
-    Ok((store, instance))
+pub fn create_linker(engine: &Engine) -> Result<Linker<StoreData>, BenchError> {
+    let mut linker = <Linker<StoreData>>::new(engine);
+    link_host_functions(&mut linker)?;
+    Ok(linker)
 }
 
-fn link_host_functions(linker: &mut Linker<()>) -> Result<(), &'static str> {
+fn link_host_functions(linker: &mut Linker<StoreData>) -> Result<(), BenchError> {
     link_logging(linker)?;
     Ok(())
 }
 
-fn link_logging(linker: &mut Linker<()>) -> Result<(), &'static str> {
+fn link_logging(linker: &mut Linker<StoreData>) -> Result<(), BenchError> {
     linker
         .func_wrap(
             "logging",
             "log",
-            |caller: Caller<'_, ()>, buffer_ptr: u32, length: u32| {
-                let memory = caller
-                    .get_export("memory")
-                    .expect("module does not export memory")
-                    .into_memory()
-                    .expect("failed to get memory");
-                let store = caller.as_context();
-                let data_start = buffer_ptr as usize;
-                let data_end = data_start + (length as usize);
-                let data = &memory.data(store)[data_start..data_end];
-
-                let log_msg = str::from_utf8(data).expect("failed to convert string");
-                defmt::info!("module log: {}", log_msg);
+            |mut caller: Caller<'_, StoreData>, buffer_ptr: u32, length: u32| {
+                let result = match caller.data().memory {
+                    Some(memory) => {
+                        let (data, state) = memory.data_and_store_mut(&mut caller);
+                        state.log_from(Some(data), buffer_ptr, length)
+                    }
+                    None => caller.data_mut().log_from(None, buffer_ptr, length),
+                };
+                result.map_err(Error::new)
             },
         )
-        .map_err(|_| "failed to link log function")?;
+        .map_err(|err| BenchError::Link(Message::new(err)))?;
     Ok(())
 }
@@ -1,15 +1,161 @@
-use wasmtime::{AsContext, Caller, Config, Engine, Func, Instance, Memory, Module, Store};
+use core::ptr::NonNull;
+
+use wasmtime::{Caller, Config, Engine, Instance, Linker, Memory, Module, Store, Val};
+
+use crate::engine::{Value, WasmEngine};
+use crate::error::{BenchError, Message};
+use crate::host::{HostState, HostStats, Limits, LogSink};
+use crate::registry::Format;
+
+/// The Pulley flavour has to match our pointer width: the boards are 32 bit, the host build is 64 bit
+const PULLEY_TARGET: &str = if cfg!(target_pointer_width = "64") {
+    "pulley64"
+} else {
+    "pulley32"
+};
+
+/// The data of our store
+type StoreData = HostState<Memory>;
 
 // Note for me: https://docs.wasmtime.dev/examples-minimal.html
 // (has a nice walkthrough how to shrink stuff - Rust stuffs in general and wasmtime in particular)
 // Other note: we pre-compile the module using the wasmtime engine. I have a project on that on my local system
 
-#[embassy_executor::task]
-pub async fn wasm_task() {
+pub struct WasmtimeEngine {
+    engine: Engine,
+    store: Store<StoreData>,
+    linker: Option<Linker<StoreData>>,
+}
+
+impl WasmEngine for WasmtimeEngine {
+    const NAME: &'static str = "wasmtime";
+    // precompiled for the Pulley flavour of `PULLEY_TARGET`
+    const FORMAT: Format = if cfg!(feature = "board-host") {
+        Format::HostCwasm
+    } else {
+        Format::Cwasm
+    };
+
+    type Module = Module;
+    type Instance = Instance;
+
+    fn new() -> Result<Self, BenchError> {
+        let state = StoreData::new(Self::NAME, Limits::default());
+        let engine = Engine::new(&engine_config(&state.limits)?)
+            .map_err(|err| BenchError::EngineInit(Message::new(err)))?;
+        let fuel = state.limits.fuel;
+        let mut store = Store::new(&engine, state);
+        if let Some(fuel) = fuel {
+            store
+                .set_fuel(fuel)
+                .map_err(|err| BenchError::EngineInit(Message::new(err)))?;
+        }
+        Ok(Self {
+            engine,
+            store,
+            linker: None,
+        })
+    }
+
+    fn load_module(&mut self, bytes: &'static [u8]) -> Result<Module, BenchError> {
+        // the module is used in place, not copied: the bytes are never modified and outlive it
+        unsafe { Module::deserialize_raw(&self.engine, NonNull::from(bytes)) }
+            .map_err(|err| BenchError::Decode(Message::new(err)))
+    }
+
+    fn link_host_functions(&mut self) -> Result<(), BenchError> {
+        let mut linker = Linker::new(&self.engine);
+        linker
+            .func_wrap("logging", "log", log)
+            .map_err(|err| BenchError::Link(Message::new(err)))?;
+        self.linker = Some(linker);
+        Ok(())
+    }
+
+    fn instantiate(&mut self, module: &mut Module) -> Result<Instance, BenchError> {
+        let linker = self
+            .linker
+            .as_ref()
+            .ok_or(BenchError::Link("host functions are not linked".into()))?;
+        let instance = linker
+            .instantiate(&mut self.store, module)
+            .map_err(|err| BenchError::Instantiate(Message::new(err)))?;
+
+        // resolved once here, so `logging.log` does not look the export up on every call
+        let memory = instance.get_memory(&mut self.store, "memory");
+        self.store.data_mut().memory = memory;
+        Ok(instance)
+    }
+
+    fn call(
+        &mut self,
+        instance: &mut Instance,
+        name: &str,
+        args: &[Value],
+    ) -> Result<Option<Value>, BenchError> {
+        let func = instance
+            .get_func(&mut self.store, name)
+            .ok_or_else(|| BenchError::MissingExport(name.into()))?;
+
+        let mut params = [Val::I32(0); 4];
+        let params = params
+            .get_mut(..args.len())
+            .ok_or(BenchError::Unsupported("too many arguments"))?;
+        for (param, arg) in params.iter_mut().zip(args) {
+            *param = to_val(*arg);
+        }
+
+        let mut results = [Val::I32(0); 1];
+        let result_count = func.ty(&self.store).results().len();
+        let results = results
+            .get_mut(..result_count)
+            .ok_or(BenchError::Unsupported("too many results"))?;
+
+        func.call(&mut self.store, params, results)
+            .map_err(|err| BenchError::Trap(Message::new(err)))?;
+        results.first().map(from_val).transpose()
+    }
+
+    fn memory_size(&mut self, _instance: &Instance) -> Option<usize> {
+        let memory = self.store.data().memory?;
+        Some(memory.data_size(&self.store))
+    }
+
+    fn set_log_sink(&mut self, sink: LogSink) {
+        self.store.data_mut().sink = sink;
+    }
+
+    fn host_stats(&mut self) -> HostStats {
+        self.store.data().stats
+    }
+}
+
+fn to_val(value: Value) -> Val {
+    match value {
+        Value::I32(value) => Val::I32(value),
+        Value::I64(value) => Val::I64(value),
+        Value::F32(value) => Val::from(value),
+        Value::F64(value) => Val::from(value),
+    }
+}
+
+fn from_val(val: &Val) -> Result<Value, BenchError> {
+    match val {
+        Val::I32(value) => Ok(Value::I32(*value)),
+        Val::I64(value) => Ok(Value::I64(*value)),
+        Val::F32(bits) => Ok(Value::F32(f32::from_bits(*bits))),
+        Val::F64(bits) => Ok(Value::F64(f64::from_bits(*bits))),
+        _ => Err(BenchError::Unsupported("result type")),
+    }
+}
+
+fn engine_config(limits: &Limits) -> Result<Config, BenchError> {
     let mut config = Config::new();
 
     // 1. Target must match
-    config.target("pulley32").expect("pulley32 target");
+    config
+        .target(PULLEY_TARGET)
+        .map_err(|err| BenchError::EngineInit(Message::new(err)))?;
 
     // 2. Match memory/trap behavior to your custom platform.
     //    Using the pattern from the min-platform example:
@@ -34,48 +180,24 @@ pub async fn wasm_task() {
     config.wasm_multi_memory(false);
 
     config.max_wasm_stack(32 * 1024);
+    config.consume_fuel(limits.fuel.is_some());
 
     // ...set others you care about explicitly too.
 
-    let engine = Engine::new(&config).expect("engine");
-
-    let precompiled = include_bytes!("../../../benchmark_module.cwasm");
-
-    let module =
-        unsafe { Module::deserialize(&engine, precompiled).expect("failed to deser module") };
-
-    let mut store = Store::new(&engine, ());
-    let log_func = Func::wrap(&mut store, log);
-
-    let instance = Instance::new(&mut store, &module, &[log_func.into()])
-        .expect("failed to instantiate module");
-
-    let run = instance
-        .get_typed_func::<(), ()>(&mut store, "run")
-        .unwrap();
-
-    run.call(&mut store, ()).unwrap();
+    Ok(config)
 }
 
-pub(super) fn log(mut caller: Caller<'_, ()>, buffer_ptr: u32, length: u32) {
-    let memory = get_memory(&mut caller);
-    let store = caller.as_context();
-    let data_start = buffer_ptr as usize;
-    let data_end = data_start + (length as usize);
-    let data = &memory.data(&store)[data_start..data_end];
-
-    let Ok(msg) = str::from_utf8(data) else {
-        defmt::error!("module logged using and invalid string");
-        return;
+pub(super) fn log(
+    mut caller: Caller<'_, StoreData>,
+    buffer_ptr: u32,
+    length: u32,
+) -> wasmtime::Result<()> {
+    let result = match caller.data().memory {
+        Some(memory) => {
+            let (data, state) = memory.data_and_store_mut(&mut caller);
+            state.log_from(Some(data), buffer_ptr, length)
+        }
+        None => caller.data_mut().log_from(None, buffer_ptr, length),
     };
-
-    defmt::info!("module msg: {}", msg);
-}
-
-fn get_memory<T>(caller: &mut Caller<'_, T>) -> Memory {
-    caller
-        .get_export("memory")
-        .expect("module memory checked during module load")
-        .into_memory()
-        .expect("module memory checked during module load")
+    result.map_err(wasmtime::Error::msg)
 }
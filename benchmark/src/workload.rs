@@ -0,0 +1,153 @@
+//! The workloads of the benchmark module and the checksums they have to return
+//!
+//! Besides `run`, the benchmark module exports a set of workloads (see
+//! `benchmark_module/src/workloads`), each taking a bounded iteration count and returning a
+//! checksum. Once all phases are done, every engine calls each workload once. The expected
+//! checksums were computed by running the same Rust code natively, so a mismatch means the engine
+//! computed something wrong.
+
+use crate::engine::{Value, WasmEngine};
+use crate::log;
+use crate::phase::{self, Measurement, PhaseReport};
+use crate::results::{self, or_unknown, ResultRecord};
+use crate::timing;
+
+/// An exported function of the benchmark module, called with a single argument
+pub struct Workload {
+    /// Name of the export, also used as the phase in the results
+    pub name: &'static str,
+    /// The iteration count (or size) we pass
+    pub arg: i32,
+    /// The checksum the call has to return
+    pub expected: i32,
+}
+
+/// The argument of every workload is kept small enough for the interpreters on the slowest board
+pub const WORKLOADS: &[Workload] = &[
+    Workload {
+        name: "crc32",
+        arg: 16,
+        expected: 0x1575_33f0,
+    },
+    Workload {
+        name: "sha256",
+        arg: 16,
+        expected: 0x1422_93b5,
+    },
+    Workload {
+        name: "sort",
+        arg: 8,
+        expected: 0xf85f_fa6e_u32 as i32,
+    },
+    Workload {
+        name: "state_machine",
+        arg: 16,
+        expected: 0x0628_51f0,
+    },
+    Workload {
+        name: "recursion",
+        arg: 20,
+        expected: 6765,
+    },
+    Workload {
+        name: "float_math",
+        arg: 10_000,
+        expected: 3_143_264,
+    },
+    // the module starts with one page, so one more makes two
+    Workload {
+        name: "memory_grow",
+        arg: 1,
+        expected: 2,
+    },
+];
+
+/// Calls every workload once and checks its checksum; returns the number of wrong checksums
+///
+/// Each call is measured like a phase and emitted as a result record with the workload name as
+/// its phase.
+pub fn run_all<E: WasmEngine>(
+    engine: &mut E,
+    instance: &mut E::Instance,
+    report: &PhaseReport,
+) -> usize {
+    log::info!(
+        "{} | workload | heap delta [B] | heap peak [B] | stack peak [B] | time [us] | {} | checksum",
+        report.engine(),
+        timing::UNIT
+    );
+
+    let mut failures = 0;
+    for workload in WORKLOADS {
+        let (result, measurement) = phase::measure(report.heap(), || {
+            engine.call(instance, workload.name, &[Value::I32(workload.arg)])
+        });
+
+        let checksum = match result {
+            Ok(Some(Value::I32(checksum))) => Some(checksum),
+            Ok(_) => {
+                log::error!("{} | {} returned no i32", report.engine(), workload.name);
+                None
+            }
+            Err(err) => {
+                log::error!(
+                    "{} | {} failed: {}",
+                    report.engine(),
+                    workload.name,
+                    measurement.classify(err)
+                );
+                None
+            }
+        };
+
+        log_measurement(report, workload, &measurement, checksum);
+        emit_measurement(report, workload, &measurement);
+
+        if checksum != Some(workload.expected) {
+            failures += 1;
+            log::error!(
+                "{} | {} returned the wrong checksum (expected {})",
+                report.engine(),
+                workload.name,
+                workload.expected
+            );
+        }
+    }
+    failures
+}
+
+fn log_measurement(
+    report: &PhaseReport,
+    workload: &Workload,
+    measurement: &Measurement,
+    checksum: Option<i32>,
+) {
+    log::info!(
+        "{} | {} | {} | {} | {} | {} | {} | {}",
+        report.engine(),
+        workload.name,
+        measurement.heap_delta,
+        measurement.heap_peak,
+        or_unknown(measurement.stack_peak),
+        measurement.duration_us,
+        or_unknown(measurement.cycles),
+        checksum.unwrap_or(-1)
+    );
+}
+
+fn emit_measurement(report: &PhaseReport, workload: &Workload, measurement: &Measurement) {
+    ResultRecord {
+        engine: report.engine(),
+        module: report.module(),
+        phase: workload.name,
+        heap_delta: measurement.heap_delta,
+        heap_peak: measurement.heap_peak,
+        heap_after: measurement.heap_after.current,
+        stack_peak: measurement.stack_peak,
+        flash_size: results::flash_size(),
+        duration_us: measurement.duration_us,
+        cycles: measurement.cycles,
+        fragmentation: measurement.fragmentation,
+    }
+    .emit();
+}
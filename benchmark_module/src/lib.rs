@@ -6,6 +6,8 @@ fn panic(_info: &PanicInfo) -> ! {
     loop {}
 }
 
+mod workloads;
+
 #[link(wasm_import_module = "logging")]
 extern "C" {
     fn log(buffer: *const u8, length: i32);
@@ -18,9 +20,46 @@ pub(crate) fn log_msg(msg: &str) {
     }
 }
 
+/// Logs `iterations` times and returns how often it did, so the host knows the call completed
 #[no_mangle]
-pub extern "C" fn run() {
-    loop {
+pub extern "C" fn run(iterations: i32) -> i32 {
+    let mut done = 0;
+    while done < iterations {
         log_msg("iterating");
+        done += 1;
+    }
+    done
+}
+
+/// Calls the `log` import `calls` times with the same message and nothing else, so the host can
+/// measure what a single host call costs
+#[no_mangle]
+pub extern "C" fn host_calls(calls: i32) -> i32 {
+    let mut done = 0;
+    while done < calls {
+        log_msg("host call");
+        done += 1;
+    }
+    done
+}
+
+/// Calls `log` with arguments that do not fit the memory, which the host has to refuse with a
+/// trap; returns 0 only if the host let the call through
+#[no_mangle]
+pub extern "C" fn log_out_of_bounds(case: i32) -> i32 {
+    let memory_end = (core::arch::wasm32::memory_size(0) * 64 * 1024) as u32;
+    let (ptr, length) = match case {
+        // starts right after the memory
+        0 => (memory_end, 1),
+        // starts inside, ends outside
+        1 => (memory_end - 4, 16),
+        // the end does not fit into 32 bits
+        2 => (u32::MAX - 3, 16),
+        // a negative length is a huge one for the host
+        _ => (0, -1),
+    };
+    unsafe {
+        log(ptr as *const u8, length);
     }
+    0
 }
@@ -0,0 +1,35 @@
+use super::{count, Lcg};
+
+const BUFFER_SIZE: usize = 1024;
+
+/// CRC-32 (IEEE) over a 1 KiB buffer, `iterations` times
+///
+/// The CRC of each round is written back into the buffer, so the rounds can not be folded.
+#[no_mangle]
+pub extern "C" fn crc32(iterations: i32) -> i32 {
+    let mut buffer = [0u8; BUFFER_SIZE];
+    let mut rng = Lcg::new(0x00C0_FFEE);
+    for byte in buffer.iter_mut() {
+        *byte = rng.next_byte();
+    }
+
+    let mut crc = 0;
+    for _ in 0..count(iterations) {
+        crc = update(crc, &buffer);
+        buffer[..4].copy_from_slice(&crc.to_le_bytes());
+    }
+    crc as i32
+}
+
+/// Bitwise CRC-32 without a lookup table: lots of shifts and masks, no memory traffic
+fn update(crc: u32, data: &[u8]) -> u32 {
+    let mut crc = !crc;
+    for &byte in data {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
@@ -0,0 +1,31 @@
+use super::count;
+
+/// The Leibniz series for pi in `f64`, followed by a Newton square root in `f32`
+///
+/// Only additions, multiplications and divisions, which Wasm defines bit-exactly, so the result is
+/// the same on every engine. Returns both results scaled to integers and added up.
+#[no_mangle]
+pub extern "C" fn float_math(iterations: i32) -> i32 {
+    let mut pi = 0.0f64;
+    let mut sign = 1.0f64;
+    for k in 0..count(iterations) {
+        pi += sign / (f64::from(k) * 2.0 + 1.0);
+        sign = -sign;
+    }
+    pi *= 4.0;
+
+    let root = sqrt(pi as f32);
+    ((pi * 1_000_000.0) as i32).wrapping_add((root * 1_000.0) as i32)
+}
+
+/// `f32::sqrt` needs std, so we do the Newton steps ourselves
+fn sqrt(value: f32) -> f32 {
+    if value <= 0.0 {
+        return 0.0;
+    }
+    let mut root = value;
+    for _ in 0..16 {
+        root = 0.5 * (root + value / root);
+    }
+    root
+}
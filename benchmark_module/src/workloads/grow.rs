@@ -0,0 +1,23 @@
+use core::arch::wasm32;
+
+const PAGE_SIZE: usize = 64 * 1024;
+
+/// Grows the linear memory by `pages` pages and writes to every word of the new pages
+///
+/// Returns the size of the memory in pages afterwards, or -1 if the engine refused to grow it.
+/// The memory does not shrink again, so this is meant to be called once per instance.
+#[no_mangle]
+pub extern "C" fn memory_grow(pages: i32) -> i32 {
+    for _ in 0..pages.max(0) {
+        let previous = wasm32::memory_grow(0, 1);
+        if previous == usize::MAX {
+            return -1;
+        }
+
+        let page = (previous * PAGE_SIZE) as *mut u32;
+        for i in 0..PAGE_SIZE / 4 {
+            unsafe { page.add(i).write_volatile(i as u32) };
+        }
+    }
+    wasm32::memory_size(0) as i32
+}
@@ -0,0 +1,37 @@
+//! The benchmark workloads
+//!
+//! Every workload is exported under its own name, takes a bounded iteration count (or size) as
+//! its only argument and returns a checksum, so the host can check that the engine computed the
+//! right thing. The inputs come from a fixed-seed generator, so they are the same on every engine.
+
+mod crc;
+mod float;
+mod grow;
+mod recursion;
+mod sha256;
+mod sort;
+mod state_machine;
+
+/// A linear congruential generator: not random at all, which is exactly what we want here
+pub(crate) struct Lcg(u32);
+
+impl Lcg {
+    pub(crate) const fn new(seed: u32) -> Self {
+        Self(seed)
+    }
+
+    pub(crate) fn next(&mut self) -> u32 {
+        self.0 = self.0.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+        self.0
+    }
+
+    /// The low bits of an LCG are poor, so bytes are taken from the top
+    pub(crate) fn next_byte(&mut self) -> u8 {
+        (self.next() >> 24) as u8
+    }
+}
+
+/// Negative iteration counts are treated as zero
+pub(crate) fn count(iterations: i32) -> u32 {
+    iterations.max(0) as u32
+}
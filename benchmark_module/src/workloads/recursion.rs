@@ -0,0 +1,17 @@
+/// The deepest recursion we allow (fib(30) already takes a few million calls)
+const MAX_DEPTH: i32 = 30;
+
+/// The naive recursive Fibonacci number of `depth`: nothing but calls and returns
+#[no_mangle]
+pub extern "C" fn recursion(depth: i32) -> i32 {
+    fib(depth.clamp(0, MAX_DEPTH) as u32) as i32
+}
+
+#[inline(never)]
+fn fib(n: u32) -> u32 {
+    if n < 2 {
+        n
+    } else {
+        fib(n - 1).wrapping_add(fib(n - 2))
+    }
+}
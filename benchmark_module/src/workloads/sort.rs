@@ -0,0 +1,25 @@
+use super::{count, Lcg};
+
+const LEN: usize = 256;
+
+/// Fills an array of 256 numbers and sorts it, `iterations` times
+///
+/// Returns a checksum over the sorted arrays, so a wrong order shows up.
+#[no_mangle]
+pub extern "C" fn sort(iterations: i32) -> i32 {
+    let mut values = [0u32; LEN];
+    let mut rng = Lcg::new(0x0000_5027);
+    let mut checksum = 0u32;
+
+    for _ in 0..count(iterations) {
+        for value in values.iter_mut() {
+            *value = rng.next();
+        }
+        values.sort_unstable();
+
+        for &value in values.iter() {
+            checksum = checksum.rotate_left(5) ^ value;
+        }
+    }
+    checksum as i32
+}
@@ -0,0 +1,93 @@
+use core::hint::black_box;
+
+use super::count;
+
+/// What the machine reads: words, numbers and punctuation
+const INPUT: &[u8] = b"measure 6 phases, 4 engines and 2 boards; \
+    heap 200000 bytes, stack 8192 bytes. run 16 workloads x 3 times, \
+    then report 42 results (or 7 errors) and stop!";
+
+#[derive(Clone, Copy)]
+enum State {
+    Idle = 0,
+    Number = 1,
+    Word = 2,
+}
+
+struct Machine {
+    state: State,
+    number: u32,
+    sum: u32,
+    words: u32,
+    numbers: u32,
+    symbols: u32,
+}
+
+/// One handler per state; they are called through this table (`call_indirect` in Wasm)
+type Handler = fn(&mut Machine, u8);
+const HANDLERS: [Handler; 3] = [idle, number, word];
+
+/// Splits the input into words, numbers and symbols, `iterations` times
+///
+/// Returns a checksum over the counts and the sum of all numbers.
+#[no_mangle]
+pub extern "C" fn state_machine(iterations: i32) -> i32 {
+    let mut machine = Machine {
+        state: State::Idle,
+        number: 0,
+        sum: 0,
+        words: 0,
+        numbers: 0,
+        symbols: 0,
+    };
+    // keeps the compiler from turning the table back into a `match`
+    let handlers = black_box(&HANDLERS);
+
+    for _ in 0..count(iterations) {
+        for &byte in INPUT.iter().chain(b" ") {
+            handlers[machine.state as usize](&mut machine, byte);
+        }
+    }
+
+    machine
+        .sum
+        .wrapping_mul(31)
+        .wrapping_add(machine.words.wrapping_mul(7))
+        .wrapping_add(machine.numbers.wrapping_mul(3))
+        .wrapping_add(machine.symbols) as i32
+}
+
+fn idle(machine: &mut Machine, byte: u8) {
+    match byte {
+        b'0'..=b'9' => {
+            machine.state = State::Number;
+            machine.number = u32::from(byte - b'0');
+        }
+        b'a'..=b'z' | b'A'..=b'Z' => machine.state = State::Word,
+        b' ' => {}
+        _ => machine.symbols = machine.symbols.wrapping_add(1),
+    }
+}
+
+fn number(machine: &mut Machine, byte: u8) {
+    if byte.is_ascii_digit() {
+        machine.number = machine
+            .number
+            .wrapping_mul(10)
+            .wrapping_add(u32::from(byte - b'0'));
+        return;
+    }
+    machine.sum = machine.sum.wrapping_add(machine.number);
+    machine.numbers = machine.numbers.wrapping_add(1);
+    machine.state = State::Idle;
+    idle(machine, byte);
+}
+
+fn word(machine: &mut Machine, byte: u8) {
+    if byte.is_ascii_alphabetic() {
+        return;
+    }
+    machine.words = machine.words.wrapping_add(1);
+    machine.state = State::Idle;
+    idle(machine, byte);
+}
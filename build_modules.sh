@@ -2,17 +2,19 @@
 set -euo pipefail
 
 SCRIPT_DIR="$(cd "$(dirname "${BASH_SOURCE[0]}")" && pwd)"
+WAMRC="$SCRIPT_DIR/third_party/wamr/wamr-compiler/build/wamrc"
 
 echo "=== Building benchmark module ==="
 cd "$SCRIPT_DIR/benchmark_module"
 cargo build --release
 
-echo "=== Precompiling module for wasmtime ==="
-cd "$SCRIPT_DIR/wasmtime_precompile"
-cargo run
+# writes every format and `modules.manifest` into the repository root; the WAMR formats only if
+# wamrc was built (see `build_wamr_aot_module.sh`)
+echo "=== Precompiling module for all engines ==="
+cd "$SCRIPT_DIR/precompile"
+cargo run --release -- \
+    "$SCRIPT_DIR/benchmark_module/target/wasm32-unknown-unknown/release/benchmark_module.wasm" \
+    --out-dir "$SCRIPT_DIR" \
+    --wamrc "$WAMRC"
 
-echo "=== Precompiling module for tinywasm ==="
-cd "$SCRIPT_DIR/tinywasm_precompile"
-cargo run
-
-echo "=== Done ==="
\ No newline at end of file
+echo "=== Done ==="
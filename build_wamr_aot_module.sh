@@ -9,6 +9,7 @@ WAMRC="$WAMRC_BUILD_DIR/wamrc"
 
 INPUT_WASM="$SCRIPT_DIR/benchmark_module/target/wasm32-unknown-unknown/release/benchmark_module.wasm"
 OUTPUT_AOT="$SCRIPT_DIR/benchmark_module.aot"
+OUTPUT_XIP_AOT="$SCRIPT_DIR/benchmark_module.xip.aot"
 
 # Step 0: Install dependencies (if needed)
 echo "=== Checking dependencies ==="
@@ -55,13 +56,10 @@ else
     echo "=== wamrc already built, skipping ==="
 fi
 
-# Step 3: Compile .wasm to .aot for nRF53 (Cortex-M33)
-echo "=== Compiling $INPUT_WASM to AOT for nRF53 ==="
-"$WAMRC" \
-    --target=thumbv7 \
-    --cpu=cortex-m33 \
-    --target-abi=gnueabihf \
-    -o "$OUTPUT_AOT" \
-    "$INPUT_WASM"
+# Step 3: Compile the module for all engines again, now with the WAMR formats (`benchmark_module.aot`
+# and `benchmark_module.xip.aot`, which runs in place from flash for the `wamr-xip` feature)
+echo "=== Compiling $INPUT_WASM for all engines, including WAMR AOT for nRF53 ==="
+cd "$SCRIPT_DIR/precompile"
+cargo run --release -- "$INPUT_WASM" --out-dir "$SCRIPT_DIR" --wamrc "$WAMRC"
 
-echo "=== Done: $OUTPUT_AOT ==="
\ No newline at end of file
+echo "=== Done: $OUTPUT_AOT, $OUTPUT_XIP_AOT ==="
@@ -0,0 +1,227 @@
+//! Mapping symbols to the crate (or C library) they come from
+
+/// Crate names and the group we report them under
+///
+/// A name also covers the crates named after it with a `_` (`wasmi` covers `wasmi_core`), but not
+/// any crate that merely starts with it (`alloc` does not cover `allocator_api2`).
+const CRATE_GROUPS: &[(&str, &str)] = &[
+    ("wasmi", "wasmi"),
+    ("tinywasm", "tinywasm"),
+    ("wasmtime", "wasmtime"),
+    ("pulley", "wasmtime"),
+    ("cranelift", "wasmtime"),
+    ("embassy", "embassy"),
+    ("defmt", "defmt"),
+    ("panic_probe", "defmt"),
+    ("cortex_m", "cortex-m"),
+    ("memory_benchmark", "memory_benchmark"),
+    ("core", "core/alloc"),
+    ("alloc", "core/alloc"),
+    ("compiler_builtins", "core/alloc"),
+];
+
+const PRIMITIVES: &[&str] = &[
+    "bool", "char", "str", "u8", "u16", "u32", "u64", "u128", "usize", "i8", "i16", "i32", "i64",
+    "i128", "isize", "f32", "f64",
+];
+
+/// Prefixes of the (unmangled) functions and data of the WAMR C library
+const WAMR_PREFIXES: &[&str] = &[
+    "wasm_",
+    "aot_",
+    "bh_",
+    "os_",
+    "gc_",
+    "ems_",
+    "hmu_",
+    "mem_allocator",
+    "invokeNative",
+    "runtime_",
+];
+
+/// Prefixes of other unmangled symbols we know the origin of
+const C_SYMBOL_GROUPS: &[(&str, &str)] = &[
+    ("_defmt", "defmt"),
+    ("__defmt", "defmt"),
+    ("_SEGGER_RTT", "defmt"),
+    ("__embassy", "embassy"),
+    ("__aeabi_", "core/alloc"),
+    ("__RESET_VECTOR", "cortex-m"),
+    ("__EXCEPTIONS", "cortex-m"),
+    ("__INTERRUPTS", "cortex-m"),
+    ("Reset", "cortex-m"),
+    ("HardFault", "cortex-m"),
+    ("DefaultHandler", "cortex-m"),
+];
+
+/// The memory functions of `compiler_builtins`, which keep their C names
+const MEM_FUNCTIONS: &[&str] = &["memcpy", "memset", "memmove", "memcmp"];
+
+/// The group a symbol is reported under
+pub fn symbol_group(name: &str) -> String {
+    match rustc_demangle::try_demangle(name) {
+        Ok(demangled) => rust_crate_group(&format!("{demangled:#}")),
+        Err(_) => c_symbol_group(name).to_string(),
+    }
+}
+
+fn rust_crate_group(demangled: &str) -> String {
+    // impl blocks look like `<wasmi::Foo as core::fmt::Debug>::fmt`, we attribute them to the type
+    let mut path = demangled.trim_start_matches(['<', '&', '*', '[', ' ']);
+    path = path
+        .strip_prefix("mut ")
+        .or_else(|| path.strip_prefix("const "))
+        .unwrap_or(path);
+    let is_identifier = |c: char| c.is_alphanumeric() || c == '_';
+    let first_segment_len = path.find(|c| !is_identifier(c)).unwrap_or(path.len());
+
+    // primitives and generics (`<str as core::fmt::Display>::fmt`) go to the crate of the trait
+    if !path[first_segment_len..].starts_with("::")
+        && let Some((_, trait_path)) = demangled.split_once(" as ")
+    {
+        path = trait_path;
+    }
+    let crate_name = path.split(|c| !is_identifier(c)).next().unwrap_or(path);
+
+    // inherent methods of primitives (`<str>::len`) are part of core
+    if crate_name.is_empty() || PRIMITIVES.contains(&crate_name) {
+        return "core/alloc".to_string();
+    }
+
+    CRATE_GROUPS
+        .iter()
+        .find(|(name, _)| {
+            crate_name
+                .strip_prefix(name)
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('_'))
+        })
+        .map(|(_, group)| group.to_string())
+        .unwrap_or_else(|| crate_name.to_string())
+}
+
+fn c_symbol_group(name: &str) -> &'static str {
+    if WAMR_PREFIXES.iter().any(|prefix| name.starts_with(prefix)) {
+        return "wamr (C)";
+    }
+    if MEM_FUNCTIONS.contains(&name) {
+        return "core/alloc";
+    }
+    C_SYMBOL_GROUPS
+        .iter()
+        .find(|(prefix, _)| name.starts_with(prefix))
+        .map(|(_, group)| *group)
+        .unwrap_or("other (C)")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rust_symbols_go_to_their_crate() {
+        let cases = [
+            ("_ZN5wasmi6engine4Func4call17h0123456789abcdefE", "wasmi"),
+            (
+                "_ZN16memory_benchmark4heap9HeapStats4mark17h0123456789abcdefE",
+                "memory_benchmark",
+            ),
+            ("_ZN4core3fmt5write17h0123456789abcdefE", "core/alloc"),
+            (
+                "_ZN5alloc7raw_vec11finish_grow17h0123456789abcdefE",
+                "core/alloc",
+            ),
+            // crates without a group of their own are reported under their name
+            (
+                "_ZN8heapless6string6String4push17h0123456789abcdefE",
+                "heapless",
+            ),
+        ];
+        for (symbol, group) in cases {
+            assert_eq!(symbol_group(symbol), group, "{symbol}");
+        }
+    }
+
+    #[test]
+    fn crates_of_an_engine_share_its_group() {
+        let cases = [
+            (
+                "_ZN18pulley_interpreter6interp11Interpreter3run17h0123456789abcdefE",
+                "wasmtime",
+            ),
+            (
+                "_ZN16cranelift_bitset6scalar12ScalarBitSet6insert17h0123456789abcdefE",
+                "wasmtime",
+            ),
+            (
+                "_ZN16embassy_executor3raw8Executor4poll17h0123456789abcdefE",
+                "embassy",
+            ),
+        ];
+        for (symbol, group) in cases {
+            assert_eq!(symbol_group(symbol), group, "{symbol}");
+        }
+    }
+
+    #[test]
+    fn impls_go_to_the_type_or_the_trait() {
+        let cases = [
+            // `<wasmi::module::Module as core::fmt::Debug>::fmt`
+            (
+                "_ZN58_$LT$wasmi..module..Module$u20$as$u20$core..fmt..Debug$GT$3fmt17h0123456789abcdefE",
+                "wasmi",
+            ),
+            // `<str as core::fmt::Display>::fmt`
+            (
+                "_ZN42_$LT$str$u20$as$u20$core..fmt..Display$GT$3fmt17h0123456789abcdefE",
+                "core/alloc",
+            ),
+            // `<str>::len`
+            ("_ZN12_$LT$str$GT$3len17h0123456789abcdefE", "core/alloc"),
+        ];
+        for (symbol, group) in cases {
+            assert_eq!(symbol_group(symbol), group, "{symbol}");
+        }
+    }
+
+    #[test]
+    fn c_symbols() {
+        let cases = [
+            ("wasm_runtime_call_wasm_a", "wamr (C)"),
+            ("aot_lookup_function", "wamr (C)"),
+            ("invokeNative", "wamr (C)"),
+            ("_SEGGER_RTT", "defmt"),
+            ("__aeabi_memcpy", "core/alloc"),
+            ("memcpy", "core/alloc"),
+            ("Reset", "cortex-m"),
+            ("HardFault", "cortex-m"),
+            ("strcmp", "other (C)"),
+            // only the memory functions themselves, not everything starting with `mem`
+            ("memmove", "core/alloc"),
+            ("memcmp", "core/alloc"),
+            ("memset", "core/alloc"),
+            ("memory_region", "other (C)"),
+            ("memchr", "other (C)"),
+        ];
+        for (symbol, group) in cases {
+            assert_eq!(symbol_group(symbol), group, "{symbol}");
+        }
+    }
+
+    #[test]
+    fn crate_names_match_exactly() {
+        let cases = [
+            // `allocator_api2` is not `alloc`, and `corosensei` is not `core`
+            (
+                "_ZN14allocator_api25alloc6Global8allocate17h0123456789abcdefE",
+                "allocator_api2",
+            ),
+            (
+                "_ZN10corosensei5stack12DefaultStack3new17h0123456789abcdefE",
+                "corosensei",
+            ),
+        ];
+        for (symbol, group) in cases {
+            assert_eq!(symbol_group(symbol), group, "{symbol}");
+        }
+    }
+}
@@ -0,0 +1,246 @@
+//! Static memory footprint of a `memory-benchmark` ELF
+//!
+//! Reports the size of the sections that end up in flash and RAM, attributes the symbols to the
+//! crates (or C libraries) they come from and separates out the embedded Wasm module.
+//!
+//! Usage: `footprint <elf> [--module <artifact>]...`
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::PathBuf;
+
+use anyhow::{Context, Result, bail};
+use object::{Object, ObjectSection, ObjectSymbol, SectionIndex};
+
+mod attribution;
+
+/// The sections we report, in the order they are printed
+const SECTIONS: &[&str] = &[
+    ".vector_table",
+    ".text",
+    ".rodata",
+    ".data",
+    ".bss",
+    ".uninit",
+];
+
+/// Sections that take up flash (`.data` is stored in flash and copied to RAM at boot)
+const FLASH_SECTIONS: &[&str] = &[".vector_table", ".text", ".rodata", ".data"];
+
+/// Sections that take up RAM
+const RAM_SECTIONS: &[&str] = &[".data", ".bss", ".uninit"];
+
+const MODULE_GROUP: &str = "embedded module";
+const UNATTRIBUTED_GROUP: &str = "(unattributed)";
+
+struct Args {
+    elf: PathBuf,
+    modules: Vec<PathBuf>,
+}
+
+/// An embedded module we found in the ELF
+struct EmbeddedModule {
+    path: PathBuf,
+    section: &'static str,
+    address: u64,
+    size: u64,
+}
+
+fn main() -> Result<()> {
+    let args = parse_args()?;
+
+    let elf_data = std::fs::read(&args.elf)
+        .with_context(|| format!("failed to read {}", args.elf.display()))?;
+    let elf = object::File::parse(&*elf_data).context("failed to parse ELF")?;
+
+    let mut section_sizes = BTreeMap::new();
+    let mut section_names = HashMap::<SectionIndex, &'static str>::new();
+    for name in SECTIONS {
+        if let Some(section) = elf.section_by_name(name) {
+            section_sizes.insert(*name, section.size());
+            section_names.insert(section.index(), *name);
+        }
+    }
+
+    let modules = find_modules(&elf, &args.modules)?;
+    let by_group = attribute_symbols(&elf, &section_names, &section_sizes, &modules);
+
+    print_sections(&section_sizes);
+    print_modules(&modules);
+    print_groups(&by_group);
+
+    Ok(())
+}
+
+fn parse_args() -> Result<Args> {
+    let mut elf = None;
+    let mut modules = Vec::new();
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--module" => {
+                let path = args.next().context("--module needs a path")?;
+                modules.push(PathBuf::from(path));
+            }
+            _ if elf.is_none() => elf = Some(PathBuf::from(arg)),
+            _ => bail!("unexpected argument '{arg}'"),
+        }
+    }
+
+    let Some(elf) = elf else {
+        bail!("usage: footprint <elf> [--module <artifact>]...");
+    };
+    Ok(Args { elf, modules })
+}
+
+/// Looks for the bytes of each module artifact in the loaded sections
+fn find_modules(elf: &object::File, paths: &[PathBuf]) -> Result<Vec<EmbeddedModule>> {
+    let mut modules = Vec::new();
+    for path in paths {
+        let blob =
+            std::fs::read(path).with_context(|| format!("failed to read {}", path.display()))?;
+        if blob.is_empty() {
+            bail!("module {} is empty", path.display());
+        }
+
+        let found = FLASH_SECTIONS.iter().find_map(|name| {
+            let section = elf.section_by_name(name)?;
+            let data = section.data().ok()?;
+            let offset = data.windows(blob.len()).position(|window| window == blob)?;
+            Some(EmbeddedModule {
+                path: path.clone(),
+                section: name,
+                address: section.address() + offset as u64,
+                size: blob.len() as u64,
+            })
+        });
+
+        match found {
+            Some(module) => modules.push(module),
+            None => eprintln!("warning: {} is not embedded in the ELF", path.display()),
+        }
+    }
+    Ok(modules)
+}
+
+/// Sums up the symbol sizes per group and section
+///
+/// Symbols overlapping an embedded module are attributed to the module. Whatever part of a section
+/// is not covered by any symbol is reported as unattributed.
+fn attribute_symbols(
+    elf: &object::File,
+    section_names: &HashMap<SectionIndex, &'static str>,
+    section_sizes: &BTreeMap<&'static str, u64>,
+    modules: &[EmbeddedModule],
+) -> BTreeMap<String, BTreeMap<&'static str, u64>> {
+    let mut by_group: BTreeMap<String, BTreeMap<&'static str, u64>> = BTreeMap::new();
+    let mut attributed: BTreeMap<&'static str, u64> = BTreeMap::new();
+    let mut seen = HashSet::new();
+
+    for module in modules {
+        *by_group
+            .entry(MODULE_GROUP.to_string())
+            .or_default()
+            .entry(module.section)
+            .or_default() += module.size;
+        *attributed.entry(module.section).or_default() += module.size;
+    }
+
+    for symbol in elf.symbols() {
+        let Some(section) = symbol
+            .section_index()
+            .and_then(|index| section_names.get(&index))
+        else {
+            continue;
+        };
+        // thumb functions have the lowest address bit set
+        let address = symbol.address() & !1;
+        if symbol.size() == 0 || !seen.insert((address, symbol.size())) {
+            continue;
+        }
+        let overlaps_module = modules.iter().any(|module| {
+            address < module.address + module.size && module.address < address + symbol.size()
+        });
+        if overlaps_module {
+            continue;
+        }
+
+        let group = attribution::symbol_group(symbol.name().unwrap_or_default());
+        *by_group
+            .entry(group)
+            .or_default()
+            .entry(section)
+            .or_default() += symbol.size();
+        *attributed.entry(section).or_default() += symbol.size();
+    }
+
+    for (section, size) in section_sizes {
+        let rest = size.saturating_sub(attributed.get(section).copied().unwrap_or(0));
+        if rest > 0 {
+            by_group
+                .entry(UNATTRIBUTED_GROUP.to_string())
+                .or_default()
+                .insert(section, rest);
+        }
+    }
+
+    by_group
+}
+
+fn print_sections(section_sizes: &BTreeMap<&'static str, u64>) {
+    println!("{:<16} {:>10}", "section", "bytes");
+    for name in SECTIONS {
+        if let Some(size) = section_sizes.get(name) {
+            println!("{name:<16} {size:>10}");
+        }
+    }
+
+    let total = |sections: &[&str]| -> u64 {
+        sections
+            .iter()
+            .filter_map(|name| section_sizes.get(name))
+            .sum()
+    };
+    println!("{:<16} {:>10}", "flash total", total(FLASH_SECTIONS));
+    println!("{:<16} {:>10}", "RAM total", total(RAM_SECTIONS));
+    println!();
+}
+
+fn print_modules(modules: &[EmbeddedModule]) {
+    if modules.is_empty() {
+        return;
+    }
+    println!(
+        "{:<40} {:<10} {:>10} {:>10}",
+        "embedded module", "section", "address", "bytes"
+    );
+    for module in modules {
+        println!(
+            "{:<40} {:<10} {:>#10x} {:>10}",
+            module.path.display(),
+            module.section,
+            module.address,
+            module.size
+        );
+    }
+    println!();
+}
+
+fn print_groups(by_group: &BTreeMap<String, BTreeMap<&'static str, u64>>) {
+    let mut groups: Vec<_> = by_group.iter().collect();
+    groups.sort_by_key(|(_, sizes)| std::cmp::Reverse(sizes.values().sum::<u64>()));
+
+    print!("{:<24}", "crate");
+    for name in SECTIONS {
+        print!(" {name:>13}");
+    }
+    println!(" {:>10}", "total");
+
+    for (group, sizes) in groups {
+        print!("{group:<24}");
+        for name in SECTIONS {
+            print!(" {:>13}", sizes.get(name).copied().unwrap_or(0));
+        }
+        println!(" {:>10}", sizes.values().sum::<u64>());
+    }
+}
@@ -0,0 +1,242 @@
+//! Turns Wasm modules into every format the benchmark engines load
+//!
+//! For every input module it writes `<module>.<format>` into the output directory, and next to
+//! them `modules.manifest`, which lists each artifact with its size and SHA-256. The firmware build
+//! embeds the modules of that manifest (see `benchmark/build.rs`).
+//!
+//! Usage: `precompile <module.wasm>... [--out-dir <dir>] [--formats <format>,...] [--wamrc <path>]
+//! [--wamr-target <target>] [--wamr-cpu <cpu>] [--wamr-abi <abi>]`
+//!
+//! Without `--formats`, all formats are written, the WAMR ones only if `wamrc` can be run.
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, bail};
+
+mod manifest;
+mod pulley;
+mod twasm;
+mod wamrc;
+
+use manifest::Entry;
+use wamrc::Wamrc;
+
+/// The formats of the manifest; the name is also the file extension
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    /// The Wasm binary as it is (wasmi)
+    Wasm,
+    /// tinywasm's archive
+    Tinywasm,
+    /// wasmtime, precompiled for pulley32 (the boards)
+    Cwasm,
+    /// wasmtime, precompiled for pulley64 (the host build)
+    HostCwasm,
+    /// WAMR, compiled by `wamrc`
+    Aot,
+    /// WAMR, compiled by `wamrc --xip` to run in place from flash
+    XipAot,
+}
+
+impl Format {
+    const ALL: &[Format] = &[
+        Format::Wasm,
+        Format::Tinywasm,
+        Format::Cwasm,
+        Format::HostCwasm,
+        Format::Aot,
+        Format::XipAot,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Format::Wasm => "wasm",
+            Format::Tinywasm => "tw",
+            Format::Cwasm => "cwasm",
+            Format::HostCwasm => "host.cwasm",
+            Format::Aot => "aot",
+            Format::XipAot => "xip.aot",
+        }
+    }
+
+    fn parse(name: &str) -> Result<Self> {
+        match Format::ALL.iter().find(|format| format.name() == name) {
+            Some(format) => Ok(*format),
+            None => bail!("unknown format '{name}'"),
+        }
+    }
+
+    fn needs_wamrc(self) -> bool {
+        matches!(self, Format::Aot | Format::XipAot)
+    }
+
+    /// Writes the module `input` (whose bytes are `wasm`) in this format to `output`
+    fn produce(self, input: &Path, wasm: &[u8], output: &Path, wamrc: &Wamrc) -> Result<()> {
+        let bytes = match self {
+            Format::Wasm => wasm.to_vec(),
+            Format::Tinywasm => twasm::archive(wasm)?,
+            Format::Cwasm => pulley::precompile(wasm, "pulley32")?,
+            Format::HostCwasm => pulley::precompile(wasm, "pulley64")?,
+            Format::Aot => return wamrc.compile(input, output, false),
+            Format::XipAot => return wamrc.compile(input, output, true),
+        };
+        std::fs::write(output, bytes)
+            .with_context(|| format!("failed to write {}", output.display()))
+    }
+}
+
+struct Args {
+    inputs: Vec<PathBuf>,
+    out_dir: PathBuf,
+    /// `None` if not given on the command line
+    formats: Option<Vec<Format>>,
+    wamrc: Wamrc,
+}
+
+fn main() -> Result<()> {
+    let args = parse_args()?;
+
+    let formats = match args.formats {
+        Some(formats) => {
+            if formats.iter().any(|format| format.needs_wamrc()) && !args.wamrc.is_available() {
+                bail!(
+                    "the WAMR formats need wamrc, but '{}' can not be run",
+                    args.wamrc.path.display()
+                );
+            }
+            formats
+        }
+        None if args.wamrc.is_available() => Format::ALL.to_vec(),
+        None => {
+            eprintln!(
+                "warning: '{}' can not be run, leaving out the WAMR formats",
+                args.wamrc.path.display()
+            );
+            Format::ALL
+                .iter()
+                .copied()
+                .filter(|format| !format.needs_wamrc())
+                .collect()
+        }
+    };
+
+    std::fs::create_dir_all(&args.out_dir)
+        .with_context(|| format!("failed to create {}", args.out_dir.display()))?;
+
+    let mut names = HashSet::new();
+    let mut entries = Vec::new();
+    for input in &args.inputs {
+        let name = module_name(input)?;
+        if !names.insert(name.clone()) {
+            bail!("two input modules are called '{name}'");
+        }
+        let wasm =
+            std::fs::read(input).with_context(|| format!("failed to read {}", input.display()))?;
+
+        for &format in &formats {
+            let file = format!("{name}.{}", format.name());
+            let output = args.out_dir.join(&file);
+            format
+                .produce(input, &wasm, &output, &args.wamrc)
+                .with_context(|| format!("failed to write '{name}' as {}", format.name()))?;
+
+            let entry = Entry::new(&name, format.name(), &file, &output)?;
+            println!("{name}: {} ({} bytes)", file, entry.size);
+            entries.push(entry);
+        }
+    }
+
+    let manifest = args.out_dir.join(manifest::FILE_NAME);
+    manifest::write(&manifest, &entries)?;
+    println!("manifest: {}", manifest.display());
+    Ok(())
+}
+
+fn parse_args() -> Result<Args> {
+    let mut inputs = Vec::new();
+    let mut out_dir = PathBuf::from(".");
+    let mut formats = None;
+    let mut wamrc = Wamrc::default();
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().with_context(|| format!("{arg} needs a value"));
+        match arg.as_str() {
+            "--out-dir" => out_dir = PathBuf::from(value()?),
+            "--formats" => {
+                formats = Some(
+                    value()?
+                        .split(',')
+                        .map(Format::parse)
+                        .collect::<Result<_>>()?,
+                );
+            }
+            "--wamrc" => wamrc.path = PathBuf::from(value()?),
+            "--wamr-target" => wamrc.target = value()?,
+            "--wamr-cpu" => wamrc.cpu = value()?,
+            "--wamr-abi" => wamrc.abi = value()?,
+            _ if arg.starts_with("--") => bail!("unknown option '{arg}'"),
+            _ => inputs.push(PathBuf::from(arg)),
+        }
+    }
+
+    if inputs.is_empty() {
+        bail!(
+            "usage: precompile <module.wasm>... [--out-dir <dir>] [--formats <format>,...] \
+             [--wamrc <path>] [--wamr-target <target>] [--wamr-cpu <cpu>] [--wamr-abi <abi>]"
+        );
+    }
+    Ok(Args {
+        inputs,
+        out_dir,
+        formats,
+        wamrc,
+    })
+}
+
+/// The name of a module in the manifest and the results: its file name without the extension
+fn module_name(input: &Path) -> Result<String> {
+    let name = input
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .with_context(|| format!("{} has no usable file name", input.display()))?;
+    if name.is_empty() || name.contains(|c: char| c.is_whitespace() || c == '#') {
+        bail!("'{name}' can not be a module name (no spaces or '#')");
+    }
+    Ok(name.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn module_name_is_the_file_stem() {
+        let name = |path: &str| module_name(Path::new(path)).ok();
+        assert_eq!(
+            name("benchmark_module.wasm").as_deref(),
+            Some("benchmark_module")
+        );
+        assert_eq!(name("../guests/other.wasm").as_deref(), Some("other"));
+        assert_eq!(name("no_extension").as_deref(), Some("no_extension"));
+    }
+
+    #[test]
+    fn module_names_the_manifest_can_not_hold() {
+        assert!(module_name(Path::new("two words.wasm")).is_err());
+        assert!(module_name(Path::new("a#b.wasm")).is_err());
+        assert!(module_name(Path::new("/")).is_err());
+    }
+
+    #[test]
+    fn formats_parse_from_their_names() {
+        for &format in Format::ALL {
+            assert_eq!(Format::parse(format.name()).unwrap(), format);
+        }
+        assert_eq!(Format::parse("host.cwasm").unwrap(), Format::HostCwasm);
+        assert_eq!(Format::parse("xip.aot").unwrap(), Format::XipAot);
+        assert!(Format::parse("cwasm64").is_err());
+        assert!(Format::parse("").is_err());
+    }
+}
@@ -0,0 +1,146 @@
+//! The manifest the firmware build reads (see `benchmark/build.rs`)
+//!
+//! One line per artifact: `<module> <format> <path> <size> <sha256>`, the path relative to the
+//! manifest. The build checks size and hash, so an artifact that changed after the manifest was
+//! written is not embedded by accident.
+
+use std::fmt::Write as _;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+
+pub const FILE_NAME: &str = "modules.manifest";
+
+/// An artifact as listed in the manifest
+pub struct Entry {
+    pub module: String,
+    pub format: &'static str,
+    /// Relative to the manifest
+    pub file: String,
+    pub size: usize,
+    /// Lower-case hex
+    pub sha256: String,
+}
+
+impl Entry {
+    /// Describes the artifact written to `path`
+    pub fn new(module: &str, format: &'static str, file: &str, path: &Path) -> Result<Self> {
+        let bytes =
+            std::fs::read(path).with_context(|| format!("failed to read {}", path.display()))?;
+        let sha256 = Sha256::digest(&bytes)
+            .iter()
+            .fold(String::new(), |mut hex, byte| {
+                let _ = write!(hex, "{byte:02x}");
+                hex
+            });
+        Ok(Self {
+            module: module.to_string(),
+            format,
+            file: file.to_string(),
+            size: bytes.len(),
+            sha256,
+        })
+    }
+}
+
+pub fn write(path: &Path, entries: &[Entry]) -> Result<()> {
+    let mut text = String::from(
+        "# Written by `precompile`, read by `benchmark/build.rs` (see `benchmark/src/registry.rs`)\n\
+         #\n\
+         # <module> <format> <path, relative to this file> <size> <sha256>\n\n",
+    );
+    for entry in entries {
+        writeln!(
+            text,
+            "{} {} {} {} {}",
+            entry.module, entry.format, entry.file, entry.size, entry.sha256
+        )?;
+    }
+    std::fs::write(path, text).with_context(|| format!("failed to write {}", path.display()))
+}
+
+/// The parser `benchmark/build.rs` reads the manifest with
+#[cfg(test)]
+#[path = "../../benchmark/manifest.rs"]
+mod build_manifest;
+
+#[cfg(test)]
+mod tests {
+    use super::build_manifest::{Line, parse_line};
+    use super::*;
+
+    #[test]
+    fn written_manifest_parses_back() {
+        let dir = std::env::temp_dir().join(format!("precompile-manifest-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let artifacts: [(&str, &str, &[u8]); 3] = [
+            ("benchmark_module", "wasm", b"\0asm\x01\0\0\0"),
+            ("benchmark_module", "host.cwasm", b"not really precompiled"),
+            ("other", "wasm", b""),
+        ];
+        let entries: Vec<_> = artifacts
+            .iter()
+            .map(|&(module, format, bytes)| {
+                let file = format!("{module}.{format}");
+                let path = dir.join(&file);
+                std::fs::write(&path, bytes).unwrap();
+                Entry::new(module, format, &file, &path).unwrap()
+            })
+            .collect();
+        let manifest = dir.join(FILE_NAME);
+        write(&manifest, &entries).unwrap();
+        let text = std::fs::read_to_string(&manifest).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let lines: Vec<_> = text
+            .lines()
+            .filter_map(|line| parse_line(line).unwrap())
+            .collect();
+        let sizes: Vec<_> = entries.iter().map(|entry| entry.size.to_string()).collect();
+        let expected: Vec<_> = entries
+            .iter()
+            .zip(&sizes)
+            .map(|(entry, size)| Line {
+                module: &entry.module,
+                format: entry.format,
+                path: &entry.file,
+                checksum: Some((size, &entry.sha256)),
+            })
+            .collect();
+        assert_eq!(lines, expected);
+        assert_eq!(
+            lines[2].checksum,
+            Some((
+                "0",
+                "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+            ))
+        );
+    }
+
+    #[test]
+    fn comments_and_short_lines() {
+        assert_eq!(parse_line("# <module> <format> <path>"), Ok(None));
+        assert_eq!(parse_line("   "), Ok(None));
+        assert_eq!(
+            parse_line("other cwasm other.cwasm 12 abcd # precompiled by hand"),
+            Ok(Some(Line {
+                module: "other",
+                format: "cwasm",
+                path: "other.cwasm",
+                checksum: Some(("12", "abcd")),
+            }))
+        );
+        // written by hand, without size and hash
+        assert_eq!(
+            parse_line("other wasm ../other.wasm"),
+            Ok(Some(Line {
+                module: "other",
+                format: "wasm",
+                path: "../other.wasm",
+                checksum: None,
+            }))
+        );
+        assert!(parse_line("other wasm other.wasm 12").is_err());
+    }
+}
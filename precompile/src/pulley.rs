@@ -0,0 +1,55 @@
+//! wasmtime: precompiled for the Pulley interpreter
+//!
+//! The configuration has to match the one the benchmark creates its engine with
+//! (`benchmark/src/wasmtime/mod.rs`), or wasmtime refuses to load the module.
+
+use anyhow::{Context, Result};
+use wasmtime::{Config, Engine};
+
+/// Precompiles `wasm` for the Pulley flavour `target` (`pulley32` or `pulley64`)
+pub fn precompile(wasm: &[u8], target: &str) -> Result<Vec<u8>> {
+    let engine = Engine::new(&config(target)?).context("failed to create the engine")?;
+    engine
+        .precompile_module(wasm)
+        .context("failed to precompile")
+}
+
+fn config(target: &str) -> Result<Config> {
+    let mut config = Config::new();
+
+    // wasmtime checks the target and these settings when it loads a precompiled module, so every
+    // setting below mirrors `engine_config` in `benchmark/src/wasmtime/mod.rs`: change both
+    // together
+    config.target(target).context("unknown pulley target")?;
+
+    // the boards have no virtual memory and no signal handlers: memories are plain allocations
+    // without copy-on-write images, reservations or guard pages, and the compiled code checks the
+    // bounds itself instead of relying on a fault
+    config.memory_init_cow(false);
+    config.memory_reservation(0);
+    config.memory_guard_size(0);
+    config.memory_reservation_for_growth(0);
+    config.signals_based_traps(false);
+
+    // small pages let a guest declare less than 64 KiB of memory; the firmware has no GC support
+    config.wasm_custom_page_sizes(true);
+    config.gc_support(false);
+
+    // proposals the firmware's engine leaves off; a module that needs one fails here instead of
+    // on the board
+    config.wasm_simd(false);
+    config.wasm_memory64(false);
+    config.wasm_relaxed_simd(false);
+    config.wasm_tail_call(false);
+    config.wasm_multi_value(false);
+    config.wasm_multi_memory(false);
+    // the firmware's wasmtime is built without the component model
+    config.wasm_component_model(false);
+
+    // the guest stack the benchmark gives the engine
+    config.max_wasm_stack(32 * 1024);
+    // fuel is metered by the compiled code; the default `Limits` of the benchmark leave it off
+    config.consume_fuel(false);
+
+    Ok(config)
+}
@@ -0,0 +1,14 @@
+//! tinywasm: the parsed module, archived so the firmware does not have to parse it
+
+use anyhow::{Result, anyhow};
+use tinywasm::parser::Parser;
+
+pub fn archive(wasm: &[u8]) -> Result<Vec<u8>> {
+    let module = Parser::default()
+        .parse_module_bytes(wasm)
+        .map_err(|err| anyhow!("failed to parse the module: {err:?}"))?;
+    let archived = module
+        .serialize_twasm()
+        .map_err(|err| anyhow!("failed to serialize the module: {err:?}"))?;
+    Ok(archived.to_vec())
+}
@@ -0,0 +1,58 @@
+//! WAMR: compiled ahead of time by `wamrc` (built by `build_wamr_aot_module.sh`)
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use anyhow::{Context, Result, bail};
+
+/// How `wamrc` is run; the defaults target the Cortex-M33 of the boards
+pub struct Wamrc {
+    pub path: PathBuf,
+    pub target: String,
+    pub cpu: String,
+    pub abi: String,
+}
+
+impl Default for Wamrc {
+    fn default() -> Self {
+        Self {
+            path: PathBuf::from("wamrc"),
+            target: "thumbv7".to_string(),
+            cpu: "cortex-m33".to_string(),
+            abi: "gnueabihf".to_string(),
+        }
+    }
+}
+
+impl Wamrc {
+    pub fn is_available(&self) -> bool {
+        Command::new(&self.path)
+            .arg("--version")
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .is_ok()
+    }
+
+    /// Compiles the module `input` to `output`; with `xip` to run in place from flash
+    pub fn compile(&self, input: &Path, output: &Path, xip: bool) -> Result<()> {
+        let mut command = Command::new(&self.path);
+        command
+            .arg(format!("--target={}", self.target))
+            .arg(format!("--cpu={}", self.cpu))
+            .arg(format!("--target-abi={}", self.abi));
+        if xip {
+            command.arg("--xip");
+        }
+        let status = command
+            .arg("-o")
+            .arg(output)
+            .arg(input)
+            .status()
+            .with_context(|| format!("failed to run {}", self.path.display()))?;
+        if !status.success() {
+            bail!("wamrc failed ({status})");
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,402 @@
+//! Turns the `@result` lines of a benchmark log into JSON or CSV
+//!
+//! The firmware emits one line per measured phase (see `benchmark/src/results.rs`). This tool picks
+//! those lines out of the log (e.g. the output of `probe-rs run`), no matter what the log viewer
+//! prefixed them with, and ignores everything else.
+//!
+//! Usage: `results_decoder [--format json|csv] [--output <file>] [<log>]`
+//! Without a log file, the log is read from stdin.
+
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+
+use anyhow::{Context, Result, bail};
+use serde::Serialize;
+
+const RECORD_MARKER: &str = "@result ";
+
+/// Keys of the records, in the order they are emitted
+///
+/// Every version only added keys at the end, so the keys of a version are a prefix of this list
+/// (see [`version_keys`]).
+const KEYS: &[&str] = &[
+    // version 1
+    "engine",
+    "board",
+    "module",
+    "phase",
+    "heap_delta",
+    "heap_peak",
+    "heap_after",
+    "stack_peak",
+    "flash_size",
+    "time_us",
+    // version 2: the cycles (nanoseconds on the host)
+    "cycles",
+    // version 3: the allocator and the heap size
+    "allocator",
+    "heap_size",
+    // version 4: the fragmentation of the heap
+    "largest_free",
+    "free_blocks",
+    "fragmentation",
+];
+
+/// The keys a record of `version` has, `None` for versions we do not know
+fn version_keys(version: &str) -> Option<&'static [&'static str]> {
+    let len = match version {
+        "v1" => 10,
+        "v2" => 11,
+        "v3" => 13,
+        "v4" => 16,
+        _ => return None,
+    };
+    Some(&KEYS[..len])
+}
+
+#[derive(Clone, Copy)]
+enum Format {
+    Json,
+    Csv,
+}
+
+struct Args {
+    format: Format,
+    input: Option<PathBuf>,
+    output: Option<PathBuf>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+struct ResultRecord {
+    engine: String,
+    board: String,
+    module: String,
+    phase: String,
+    heap_delta: i64,
+    heap_peak: u64,
+    heap_after: u64,
+    stack_peak: Option<u64>,
+    flash_size: Option<u64>,
+    time_us: u64,
+    /// Missing in version 1 records
+    cycles: Option<u64>,
+    /// Missing before version 3
+    allocator: Option<String>,
+    /// Missing before version 3
+    heap_size: Option<u64>,
+    /// Missing before version 4
+    largest_free: Option<u64>,
+    /// Missing before version 4
+    free_blocks: Option<u64>,
+    /// In per mille; missing before version 4
+    fragmentation: Option<u64>,
+}
+
+fn main() -> Result<()> {
+    let args = parse_args()?;
+
+    let input: Box<dyn BufRead> = match &args.input {
+        Some(path) => Box::new(BufReader::new(
+            std::fs::File::open(path)
+                .with_context(|| format!("failed to open {}", path.display()))?,
+        )),
+        None => Box::new(std::io::stdin().lock()),
+    };
+
+    let mut records = Vec::new();
+    for (index, line) in input.lines().enumerate() {
+        let line = line.context("failed to read log")?;
+        let Some(record) = find_record(&line) else {
+            continue;
+        };
+        let record = parse_record(record)
+            .with_context(|| format!("invalid record in line {}", index + 1))?;
+        records.push(record);
+    }
+
+    let mut output: Box<dyn Write> = match &args.output {
+        Some(path) => Box::new(
+            std::fs::File::create(path)
+                .with_context(|| format!("failed to create {}", path.display()))?,
+        ),
+        None => Box::new(std::io::stdout().lock()),
+    };
+    match args.format {
+        Format::Json => {
+            serde_json::to_writer_pretty(&mut output, &records)?;
+            writeln!(output)?;
+        }
+        Format::Csv => write_csv(&mut output, &records)?,
+    }
+
+    eprintln!("decoded {} records", records.len());
+    Ok(())
+}
+
+fn parse_args() -> Result<Args> {
+    let mut format = Format::Json;
+    let mut input = None;
+    let mut output = None;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--format" => {
+                format = match args.next().as_deref() {
+                    Some("json") => Format::Json,
+                    Some("csv") => Format::Csv,
+                    _ => bail!("--format needs to be 'json' or 'csv'"),
+                };
+            }
+            "--output" => {
+                let path = args.next().context("--output needs a path")?;
+                output = Some(PathBuf::from(path));
+            }
+            _ if input.is_none() => input = Some(PathBuf::from(arg)),
+            _ => bail!("unexpected argument '{arg}'"),
+        }
+    }
+
+    Ok(Args {
+        format,
+        input,
+        output,
+    })
+}
+
+/// The part of a log line after the record marker, `None` if the line is no record
+fn find_record(line: &str) -> Option<&str> {
+    line.split_once(RECORD_MARKER).map(|(_, record)| record)
+}
+
+/// Parses everything after the marker, i.e. `v4 engine=... board=... ...`
+fn parse_record(record: &str) -> Result<ResultRecord> {
+    let mut tokens = record.split_whitespace();
+    let Some(version) = tokens.next() else {
+        bail!("record is empty");
+    };
+    let Some(keys) = version_keys(version) else {
+        bail!("unsupported record version '{version}'");
+    };
+
+    let mut values = HashMap::new();
+    for token in tokens {
+        let Some((key, value)) = token.split_once('=') else {
+            bail!("expected 'key=value', got '{token}'");
+        };
+        values.insert(key, value);
+    }
+    for key in keys {
+        if !values.contains_key(key) {
+            bail!("missing key '{key}'");
+        }
+    }
+
+    let text = |key: &str| values[key].to_string();
+    let number = |key: &str| -> Result<i64> {
+        values[key]
+            .parse()
+            .with_context(|| format!("'{key}' is not a number"))
+    };
+    let unsigned = |key: &str| -> Result<u64> {
+        u64::try_from(number(key)?).with_context(|| format!("'{key}' is negative"))
+    };
+    // the firmware emits -1 for values it could not measure
+    let optional = |key: &str| -> Result<Option<u64>> { Ok(u64::try_from(number(key)?).ok()) };
+    // keys that older versions do not have
+    let added = |key: &str| -> Result<Option<u64>> {
+        if values.contains_key(key) {
+            optional(key)
+        } else {
+            Ok(None)
+        }
+    };
+
+    Ok(ResultRecord {
+        engine: text("engine"),
+        board: text("board"),
+        module: text("module"),
+        phase: text("phase"),
+        heap_delta: number("heap_delta")?,
+        heap_peak: unsigned("heap_peak")?,
+        heap_after: unsigned("heap_after")?,
+        stack_peak: optional("stack_peak")?,
+        flash_size: optional("flash_size")?,
+        time_us: unsigned("time_us")?,
+        cycles: added("cycles")?,
+        allocator: values.contains_key("allocator").then(|| text("allocator")),
+        heap_size: if values.contains_key("heap_size") {
+            Some(unsigned("heap_size")?)
+        } else {
+            None
+        },
+        largest_free: added("largest_free")?,
+        free_blocks: added("free_blocks")?,
+        fragmentation: added("fragmentation")?,
+    })
+}
+
+fn write_csv(output: &mut dyn Write, records: &[ResultRecord]) -> Result<()> {
+    writeln!(output, "{}", KEYS.join(","))?;
+
+    let optional = |value: Option<u64>| value.map(|value| value.to_string()).unwrap_or_default();
+    for record in records {
+        writeln!(
+            output,
+            "{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{}",
+            record.engine,
+            record.board,
+            record.module,
+            record.phase,
+            record.heap_delta,
+            record.heap_peak,
+            record.heap_after,
+            optional(record.stack_peak),
+            optional(record.flash_size),
+            record.time_us,
+            optional(record.cycles),
+            record.allocator.as_deref().unwrap_or_default(),
+            optional(record.heap_size),
+            optional(record.largest_free),
+            optional(record.free_blocks),
+            optional(record.fragmentation)
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const V1: &str = "v1 engine=wasmi board=nrf5340 module=benchmark_module phase=load \
+                      heap_delta=-12 heap_peak=4096 heap_after=2048 stack_peak=-1 \
+                      flash_size=123456 time_us=789";
+
+    /// The record as the current firmware emits it, with the values of [`V1`]
+    fn v4() -> String {
+        format!(
+            "v4{} cycles=5000 allocator=llff heap_size=200000 largest_free=150000 \
+             free_blocks=3 fragmentation=42",
+            V1.strip_prefix("v1").unwrap()
+        )
+    }
+
+    fn v1_record() -> ResultRecord {
+        ResultRecord {
+            engine: "wasmi".into(),
+            board: "nrf5340".into(),
+            module: "benchmark_module".into(),
+            phase: "load".into(),
+            heap_delta: -12,
+            heap_peak: 4096,
+            heap_after: 2048,
+            stack_peak: None,
+            flash_size: Some(123456),
+            time_us: 789,
+            cycles: None,
+            allocator: None,
+            heap_size: None,
+            largest_free: None,
+            free_blocks: None,
+            fragmentation: None,
+        }
+    }
+
+    #[test]
+    fn every_version_parses() {
+        let v1 = v1_record();
+        assert_eq!(parse_record(V1).unwrap(), v1);
+
+        let v2 = format!("v2{} cycles=5000", V1.strip_prefix("v1").unwrap());
+        let v2_record = ResultRecord {
+            cycles: Some(5000),
+            ..v1_record()
+        };
+        assert_eq!(parse_record(&v2).unwrap(), v2_record);
+
+        let v3 = format!(
+            "v3{} allocator=llff heap_size=200000",
+            v2.strip_prefix("v2").unwrap()
+        );
+        let v3_record = ResultRecord {
+            allocator: Some("llff".into()),
+            heap_size: Some(200000),
+            ..v2_record
+        };
+        assert_eq!(parse_record(&v3).unwrap(), v3_record);
+
+        let v4_record = ResultRecord {
+            largest_free: Some(150000),
+            free_blocks: Some(3),
+            fragmentation: Some(42),
+            ..v3_record
+        };
+        assert_eq!(parse_record(&v4()).unwrap(), v4_record);
+    }
+
+    #[test]
+    fn keys_may_come_in_any_order() {
+        let mut tokens: Vec<_> = v4().split_whitespace().map(str::to_string).collect();
+        tokens[1..].reverse();
+        assert_eq!(
+            parse_record(&tokens.join(" ")).unwrap(),
+            parse_record(&v4()).unwrap()
+        );
+    }
+
+    #[test]
+    fn missing_key_is_an_error() {
+        let record = V1.replace(" time_us=789", "");
+        let error = parse_record(&record).unwrap_err();
+        assert!(error.to_string().contains("time_us"), "{error}");
+
+        // a v4 record has to carry the keys v4 added
+        let record = v4().replace(" fragmentation=42", "");
+        assert!(parse_record(&record).is_err());
+    }
+
+    #[test]
+    fn unknown_values_are_none() {
+        let record = v4()
+            .replace("flash_size=123456", "flash_size=-1")
+            .replace("cycles=5000", "cycles=-1")
+            .replace("largest_free=150000", "largest_free=-1");
+        let record = parse_record(&record).unwrap();
+        assert_eq!(record.stack_peak, None);
+        assert_eq!(record.flash_size, None);
+        assert_eq!(record.cycles, None);
+        assert_eq!(record.largest_free, None);
+        // measured values must not be unknown
+        assert!(parse_record(&V1.replace("heap_peak=4096", "heap_peak=-1")).is_err());
+    }
+
+    #[test]
+    fn newest_version_has_all_keys() {
+        assert_eq!(version_keys("v4"), Some(KEYS));
+        assert_eq!(version_keys("v1").unwrap().last(), Some(&"time_us"));
+        assert_eq!(version_keys("v3").unwrap().last(), Some(&"heap_size"));
+    }
+
+    #[test]
+    fn invalid_records() {
+        assert!(parse_record("").is_err());
+        assert!(parse_record(&V1.replacen("v1", "v9", 1)).is_err());
+        assert!(parse_record(&V1.replace("time_us=789", "time_us")).is_err());
+        assert!(parse_record(&V1.replace("time_us=789", "time_us=soon")).is_err());
+    }
+
+    #[test]
+    fn records_are_found_behind_any_prefix() {
+        let line = format!(
+            "[INFO ] 12.345678 memory_benchmark::results @result {}",
+            v4()
+        );
+        let record = find_record(&line).unwrap();
+        assert_eq!(parse_record(record).unwrap(), parse_record(&v4()).unwrap());
+
+        assert_eq!(find_record(&format!("@result {V1}")), Some(V1));
+        assert_eq!(find_record("[INFO ] heap after load: current 2048"), None);
+    }
+}